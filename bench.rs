@@ -0,0 +1,49 @@
+use std::time::Instant;
+
+use crate::compiler::Lexer;
+
+// Lexer throughput on generated sources of a few megabytes each, one per
+// kind of input that dominates real .27 files. Build with optimizations:
+//     rustc -O main.rs && ./main --bench [file.27]
+const TARGET_SIZE: usize = 4 * 1024 * 1024;
+const ROUNDS: usize = 5;
+
+pub fn run(extra_file: Option<String>) {
+    let mut cases = vec![
+        ("program", repeat_to_size(include_str!("test_code.27"))),
+        ("identifiers", repeat_to_size("b32 some_long_identifier_name; counter = counter + step_size;\n")),
+        ("numbers", repeat_to_size("x = 0xDEAD_BEEF + 1_000_000 * 0b1010_1010 - 255b8 + 0o777;\n")),
+        ("strings", repeat_to_size("print(\"a string with \\\"escapes\\\" and \\u{e9} in it\\n\");\n")),
+        ("comments", repeat_to_size("// a line comment\n/* a /* nested */ block comment */\n/// doc\n")),
+    ];
+    if let Some(path) = extra_file {
+        let content = std::fs::read_to_string(&path).expect("Should read file");
+        cases.push(("file", content));
+    }
+
+    for (name, source) in cases.iter() {
+        let mut tokens = 0;
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            tokens = 0;
+            for token in Lexer::new(source) {
+                if let Err(error) = token {
+                    panic!("{}", error);
+                }
+                tokens += 1;
+            }
+        }
+        let seconds = start.elapsed().as_secs_f64() / ROUNDS as f64;
+        let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+        println!("{:<12} {:>8.2} MB {:>10} tokens {:>10.2} MB/s",
+            name, megabytes, tokens, megabytes / seconds);
+    }
+}
+
+fn repeat_to_size(chunk: &str) -> String {
+    let mut source = String::with_capacity(TARGET_SIZE + chunk.len());
+    while source.len() < TARGET_SIZE {
+        source.push_str(chunk);
+    }
+    source
+}
@@ -0,0 +1 @@
+rustc -O main.rs -o main_bench && ./main_bench --bench "$@"
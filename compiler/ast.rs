@@ -0,0 +1,381 @@
+use crate::compiler::TokenType;
+use crate::compiler::interner::Symbol;
+use crate::compiler::types::Type;
+
+// Index of a node in the Ast arena. Nodes refer to each other only through
+// these, so the whole tree is a single allocation and two references to the
+// same node compare equal in O(1).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(u32);
+
+// `tp` is the type of an expression node, filled in by the parser once the
+// expression has been checked. `binding` is where the variable a node
+// declares or refers to lives, resolved by the parser as well.
+#[derive(Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub line: usize,
+    pub tp: Option<Type>,
+    pub binding: Option<Binding>,
+}
+
+// Slot of a variable when the program runs: one of the globals, or one of
+// the frame of the running function. Every declaration has a slot of its own,
+// so a name always means the variable that was in scope where it is written.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+    Global(usize),
+    Local(usize),
+}
+
+#[derive(Debug)]
+pub enum NodeKind {
+    Program(Vec<NodeId>),
+    FuncDecl { name: Symbol, params: Vec<NodeId>, ret: Option<Type>, body: Vec<NodeId> },
+    Param { name: Symbol, tp: Type },
+    // Array parameters without a length accept arrays of any length
+    ArrayParam { name: Symbol, tp: Type, len: Option<usize> },
+    VarDecl { name: Symbol, tp: Type, init: Option<NodeId> },
+    // Value computed when the program is compiled
+    ConstDecl { name: Symbol, tp: Type, value: i128 },
+    // `b8 a = 1, b = 2;`, one declaration per name
+    Declarations(Vec<NodeId>),
+    ArrayDecl { name: Symbol, tp: Type, len: usize },
+    StructDecl { name: Symbol, fields: Vec<NodeId>, size: usize, align: usize },
+    FieldDecl { name: Symbol, tp: Type, offset: usize },
+    StructFieldDecl { name: Symbol, struct_name: Symbol, offset: usize },
+    // `slots` is the number of integers the struct flattens to
+    StructVarDecl { name: Symbol, struct_name: Symbol, slots: usize },
+    StructParam { name: Symbol, struct_name: Symbol },
+    Assign { target: Symbol, value: NodeId },
+    IndexAssign { target: Symbol, index: NodeId, value: NodeId },
+    // `slot` is the position of the field in the flattened struct, `tp` its type
+    FieldAssign { target: Symbol, path: Vec<Symbol>, slot: usize, tp: Type, value: NodeId },
+    Call { callee: Symbol, args: Vec<NodeId> },
+    If { condition: NodeId, then_body: Vec<NodeId>, else_body: Vec<NodeId> },
+    // Loops carry their label, if they have one
+    While { condition: NodeId, body: Vec<NodeId>, label: Option<Symbol> },
+    // A missing condition is always true
+    For { init: Option<NodeId>, condition: Option<NodeId>, step: Option<NodeId>, body: Vec<NodeId>, label: Option<Symbol> },
+    DoWhile { body: Vec<NodeId>, condition: NodeId, label: Option<Symbol> },
+    // `format` is the string as written, `pieces` the same string split
+    // around its placeholders, one for each of `args`. `stderr` for eprint and eprintln.
+    Print { format: Symbol, pieces: Vec<FormatPiece>, args: Vec<NodeId>, newline: bool, stderr: bool },
+    // `default` is the index of the `_` arm, if there is one
+    Match { scrutinee: NodeId, arms: Vec<NodeId>, default: Option<usize>, dispatch: Dispatch },
+    // Patterns as inclusive ranges of values, empty for `_`
+    MatchArm { patterns: Vec<(i128, i128)>, body: Vec<NodeId> },
+    // Label of the loop to leave or continue, the innermost one if None
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+    Return(Option<NodeId>),
+    Number(u128),
+    Bool(bool),
+    Identifier(Symbol),
+    Index { array: Symbol, index: NodeId },
+    FieldAccess { base: Symbol, path: Vec<Symbol>, slot: usize },
+    Unary { op: TokenType, operand: NodeId },
+    Binary { op: TokenType, left: NodeId, right: NodeId },
+    Convert { tp: Type, operand: NodeId },
+}
+
+#[derive(Debug)]
+pub enum FormatPiece {
+    Text(String),
+    Argument(FormatSpec),
+}
+
+// What goes between the braces of a placeholder, `{:08b}`: the value is
+// written as `kind` says, padded on the left to `width` characters with
+// zeroes or spaces. `d` is decimal, `x` and `X` hexadecimal, `o` octal, `b`
+// binary and `c` the character with the value as its code point.
+// Hexadecimal, octal and binary show the bits of the value's type, so
+// negative numbers come out in two's complement.
+#[derive(Clone, Copy, Debug)]
+pub struct FormatSpec {
+    pub kind: char,
+    pub zero: bool,
+    pub width: usize,
+}
+
+impl FormatSpec {
+    // `{}`
+    pub fn decimal() -> FormatSpec {
+        FormatSpec { kind: 'd', zero: false, width: 0 }
+    }
+}
+
+// Patterns spanning at most this many values, of which at least half are
+// matched by some arm, are looked up in a table instead of searched
+const JUMP_TABLE_LIMIT: u128 = 1024;
+
+// How a match finds the arm for a value, given as its order key (see
+// Type::order_key). Arms are indices into the match's arm list.
+#[derive(Debug)]
+pub enum Dispatch {
+    // One entry per value from `low` on
+    Table { low: u128, arms: Vec<Option<usize>> },
+    // Key ranges sorted by their start, for a binary search
+    Ranges(Vec<(u128, u128, usize)>),
+}
+
+impl Dispatch {
+    // `ranges` are the inclusive key ranges of every pattern and their arm,
+    // none of them overlapping
+    pub fn new(mut ranges: Vec<(u128, u128, usize)>) -> Dispatch {
+        ranges.sort();
+        let (low, high) = match (ranges.first(), ranges.last()) {
+            (Some(first), Some(last)) => (first.0, last.1),
+            _ => return Dispatch::Ranges(ranges),
+        };
+        let span = (high - low).saturating_add(1);
+        let covered = ranges.iter().fold(0u128, |covered, &(start, end, _)| covered.saturating_add((end - start).saturating_add(1)));
+        if span > JUMP_TABLE_LIMIT || covered * 2 < span {
+            return Dispatch::Ranges(ranges);
+        }
+        let mut arms = vec![None; span as usize];
+        for &(start, end, arm) in ranges.iter() {
+            for entry in arms[(start - low) as usize..=(end - low) as usize].iter_mut() {
+                *entry = Some(arm);
+            }
+        }
+        Dispatch::Table { low, arms }
+    }
+
+    pub fn arm(&self, key: u128) -> Option<usize> {
+        match *self {
+            Dispatch::Table { low, ref arms } => {
+                key.checked_sub(low).and_then(|index| arms.get(index as usize)).cloned().unwrap_or(None)
+            }
+            Dispatch::Ranges(ref ranges) => {
+                let index = ranges.partition_point(|&(start, _, _)| start <= key);
+                match index.checked_sub(1).map(|index| ranges[index]) {
+                    Some((_, end, arm)) if key <= end => Some(arm),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Ast {
+    nodes: Vec<Node>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Ast { nodes: Vec::new() }
+    }
+
+    pub fn add(&mut self, kind: NodeKind, line: usize) -> NodeId {
+        self.nodes.push(Node { kind, line, tp: None, binding: None });
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+
+    pub fn set_type(&mut self, id: NodeId, tp: Type) {
+        self.nodes[id.0 as usize].tp = Some(tp);
+    }
+
+    pub fn set_binding(&mut self, id: NodeId, binding: Binding) {
+        self.nodes[id.0 as usize].binding = Some(binding);
+    }
+
+    pub fn get(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
+
+    // Whether running `body` always ends in a `return`: every path through
+    // it reaches one, or it loops forever. Only loops whose condition is
+    // `true` or missing, and that no `break` leaves, count as endless.
+    pub fn always_returns(&self, body: &[NodeId]) -> bool {
+        body.iter().any(|&id| match self.get(id).kind {
+            NodeKind::Return(_) => true,
+            NodeKind::If { ref then_body, ref else_body, .. } => {
+                self.always_returns(then_body) && self.always_returns(else_body)
+            }
+            NodeKind::While { condition, ref body, .. } => self.is_true(Some(condition)) && !self.breaks(body, false),
+            NodeKind::For { condition, ref body, .. } => self.is_true(condition) && !self.breaks(body, false),
+            // every value has an arm
+            NodeKind::Match { ref arms, .. } => arms.iter().all(|&arm| self.always_returns(self.arm_body(arm))),
+            NodeKind::DoWhile { ref body, condition, .. } => {
+                self.always_returns(body) || self.is_true(Some(condition)) && !self.breaks(body, false)
+            }
+            _ => false,
+        })
+    }
+
+    pub fn arm_body(&self, arm: NodeId) -> &[NodeId] {
+        match self.get(arm).kind {
+            NodeKind::MatchArm { ref body, .. } => body,
+            _ => panic!("Expected a match arm"),
+        }
+    }
+
+    pub fn is_true(&self, condition: Option<NodeId>) -> bool {
+        matches!(condition.map(|condition| &self.get(condition).kind), None | Some(&NodeKind::Bool(true)))
+    }
+
+    // Whether `body` contains a `break` that may leave the loop it is in.
+    // Inside `nested` loops only labeled breaks can, and they are assumed to.
+    fn breaks(&self, body: &[NodeId], nested: bool) -> bool {
+        body.iter().any(|&id| match self.get(id).kind {
+            NodeKind::Break(label) => !nested || label.is_some(),
+            NodeKind::If { ref then_body, ref else_body, .. } => {
+                self.breaks(then_body, nested) || self.breaks(else_body, nested)
+            }
+            NodeKind::Match { ref arms, .. } => arms.iter().any(|&arm| self.breaks(self.arm_body(arm), nested)),
+            NodeKind::While { ref body, .. } | NodeKind::For { ref body, .. } | NodeKind::DoWhile { ref body, .. } => {
+                self.breaks(body, true)
+            }
+            _ => false,
+        })
+    }
+
+    // Indented outline of the tree below `id`, one node per line.
+    pub fn dump(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        self.dump_into(id, 0, &mut out);
+        out
+    }
+
+    fn dump_into(&self, id: NodeId, depth: usize, out: &mut String) {
+        let node = self.get(id);
+        let children: Vec<NodeId> = match node.kind {
+            NodeKind::Program(ref body) | NodeKind::Declarations(ref body) => body.clone(),
+            NodeKind::VarDecl { init, .. } => init.into_iter().collect(),
+            NodeKind::StructDecl { ref fields, .. } => fields.clone(),
+            NodeKind::FieldAssign { value, .. } => vec![value],
+            NodeKind::FuncDecl { ref params, ref body, .. } => params.iter().chain(body.iter()).cloned().collect(),
+            NodeKind::Assign { value, .. } => vec![value],
+            NodeKind::IndexAssign { index, value, .. } => vec![index, value],
+            NodeKind::Index { index, .. } => vec![index],
+            NodeKind::Call { ref args, .. } => args.clone(),
+            NodeKind::If { condition, ref then_body, ref else_body } => {
+                Some(condition).into_iter().chain(then_body.iter().cloned()).chain(else_body.iter().cloned()).collect()
+            }
+            NodeKind::While { condition, ref body, .. } => Some(condition).into_iter().chain(body.iter().cloned()).collect(),
+            NodeKind::For { init, condition, step, ref body, .. } => {
+                init.into_iter().chain(condition).chain(step).chain(body.iter().cloned()).collect()
+            }
+            NodeKind::DoWhile { ref body, condition, .. } => body.iter().cloned().chain(Some(condition)).collect(),
+            NodeKind::Match { scrutinee, ref arms, .. } => Some(scrutinee).into_iter().chain(arms.iter().cloned()).collect(),
+            NodeKind::MatchArm { ref body, .. } => body.clone(),
+            NodeKind::Print { ref args, .. } => args.clone(),
+            NodeKind::Return(value) => value.into_iter().collect(),
+            NodeKind::Unary { operand, .. } | NodeKind::Convert { operand, .. } => vec![operand],
+            NodeKind::Binary { left, right, .. } => vec![left, right],
+            _ => Vec::new(),
+        };
+        let label = match node.kind {
+            NodeKind::Program(_) => "Program".to_string(),
+            NodeKind::FuncDecl { name, ret: Some(ret), .. } => format!("FuncDecl {} -> {}", name, ret),
+            NodeKind::FuncDecl { name, .. } => format!("FuncDecl {}", name),
+            NodeKind::Param { name, tp } => format!("Param {} {}", tp, name),
+            NodeKind::ArrayParam { name, tp, len: Some(len) } => format!("ArrayParam {}[{}] {}", tp, len, name),
+            NodeKind::ArrayParam { name, tp, len: None } => format!("ArrayParam {}[] {}", tp, name),
+            NodeKind::VarDecl { name, tp, .. } => format!("VarDecl {} {}", tp, name),
+            NodeKind::ConstDecl { name, tp, value } => format!("ConstDecl {} {} = {}", tp, name, value),
+            NodeKind::Declarations(_) => "Declarations".to_string(),
+            NodeKind::ArrayDecl { name, tp, len } => format!("ArrayDecl {}[{}] {}", tp, len, name),
+            NodeKind::StructDecl { name, size, align, .. } => format!("StructDecl {} (size {}, align {})", name, size, align),
+            NodeKind::FieldDecl { name, tp, offset } => format!("FieldDecl {} {} (offset {})", tp, name, offset),
+            NodeKind::StructFieldDecl { name, struct_name, offset } => {
+                format!("FieldDecl {} {} (offset {})", struct_name, name, offset)
+            }
+            NodeKind::StructVarDecl { name, struct_name, .. } => format!("VarDecl {} {}", struct_name, name),
+            NodeKind::StructParam { name, struct_name, .. } => format!("Param {} {}", struct_name, name),
+            NodeKind::FieldAssign { target, ref path, .. } => format!("FieldAssign {}.{}", target, join(path)),
+            NodeKind::FieldAccess { base, ref path, .. } => format!("FieldAccess {}.{}", base, join(path)),
+            NodeKind::Assign { target, .. } => format!("Assign {}", target),
+            NodeKind::IndexAssign { target, .. } => format!("IndexAssign {}", target),
+            NodeKind::Index { array, .. } => format!("Index {}", array),
+            NodeKind::Call { callee, .. } => format!("Call {}", callee),
+            NodeKind::If { .. } => "If".to_string(),
+            NodeKind::While { label, .. } => labeled("While", label),
+            NodeKind::For { label, .. } => labeled("For", label),
+            NodeKind::DoWhile { label, .. } => labeled("DoWhile", label),
+            NodeKind::Match { ref dispatch, .. } => match *dispatch {
+                Dispatch::Table { ref arms, .. } => format!("Match (jump table of {})", arms.len()),
+                Dispatch::Ranges(ref ranges) => format!("Match (search of {} ranges)", ranges.len()),
+            },
+            NodeKind::MatchArm { ref patterns, .. } if patterns.is_empty() => "Arm _".to_string(),
+            NodeKind::MatchArm { ref patterns, .. } => {
+                let patterns: Vec<String> = patterns.iter().map(|&(start, end)| {
+                    if start == end { start.to_string() } else { format!("{}..={}", start, end) }
+                }).collect();
+                format!("Arm {}", patterns.join(" | "))
+            }
+            NodeKind::Break(label) => labeled("Break", label),
+            NodeKind::Continue(label) => labeled("Continue", label),
+            NodeKind::Print { format, newline, stderr, .. } => {
+                format!("{}{} {:?}", if stderr { "Eprint" } else { "Print" }, if newline { "ln" } else { "" }, format.as_str())
+            }
+            NodeKind::Return(_) => "Return".to_string(),
+            NodeKind::Number(value) => format!("Number {}", value),
+            NodeKind::Bool(value) => format!("Bool {}", value),
+            NodeKind::Identifier(name) => format!("Identifier {}", name),
+            NodeKind::Unary { op, .. } => format!("Unary {:?}", op),
+            NodeKind::Binary { op, .. } => format!("Binary {:?}", op),
+            NodeKind::Convert { tp, .. } => format!("Convert {}", tp),
+        };
+        out.push_str(&format!("{}{} (line {})\n", "  ".repeat(depth), label, node.line));
+        for child in children {
+            self.dump_into(child, depth + 1, out);
+        }
+    }
+}
+
+fn join(path: &[Symbol]) -> String {
+    path.iter().map(|field| field.as_str()).collect::<Vec<_>>().join(".")
+}
+
+fn labeled(kind: &str, label: Option<Symbol>) -> String {
+    match label {
+        Some(label) => format!("{} {}", kind, label),
+        None => kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dispatch, JUMP_TABLE_LIMIT};
+
+    fn arms(dispatch: &Dispatch, keys: &[u128]) -> Vec<Option<usize>> {
+        keys.iter().map(|&key| dispatch.arm(key)).collect()
+    }
+
+    #[test]
+    fn dense_patterns_get_a_table() {
+        let dispatch = Dispatch::new(vec![(12, 14, 1), (10, 11, 0), (16, 16, 2)]);
+        assert!(matches!(dispatch, Dispatch::Table { low: 10, ref arms } if arms.len() == 7));
+        assert_eq!(arms(&dispatch, &[9, 10, 11, 14, 15, 16, 17]), [None, Some(0), Some(0), Some(1), None, Some(2), None]);
+    }
+
+    #[test]
+    fn a_table_needs_half_its_span_covered() {
+        // 5 of 10 values
+        let half = Dispatch::new(vec![(0, 2, 0), (7, 8, 1)]);
+        assert!(matches!(half, Dispatch::Table { .. }));
+        // 4 of 10 values
+        let sparse = Dispatch::new(vec![(0, 1, 0), (8, 9, 1)]);
+        assert!(matches!(sparse, Dispatch::Ranges(_)));
+        assert_eq!(arms(&sparse, &[0, 1, 2, 7, 8, 9, 10]), [Some(0), Some(0), None, None, Some(1), Some(1), None]);
+    }
+
+    #[test]
+    fn a_table_spans_at_most_the_limit() {
+        let full = Dispatch::new(vec![(0, JUMP_TABLE_LIMIT - 1, 0)]);
+        assert!(matches!(full, Dispatch::Table { ref arms, .. } if arms.len() == JUMP_TABLE_LIMIT as usize));
+        let over = Dispatch::new(vec![(0, JUMP_TABLE_LIMIT, 0)]);
+        assert!(matches!(over, Dispatch::Ranges(_)));
+        assert_eq!(arms(&over, &[0, JUMP_TABLE_LIMIT, JUMP_TABLE_LIMIT + 1]), [Some(0), Some(0), None]);
+    }
+
+    #[test]
+    fn ranges_reach_the_ends_of_the_key_space() {
+        let dispatch = Dispatch::new(vec![(1 << 127, u128::MAX, 1), (0, (1 << 127) - 1, 0)]);
+        assert!(matches!(dispatch, Dispatch::Ranges(_)));
+        assert_eq!(arms(&dispatch, &[0, (1 << 127) - 1, 1 << 127, u128::MAX]), [Some(0), Some(0), Some(1), Some(1)]);
+    }
+}
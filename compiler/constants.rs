@@ -1,6 +1,102 @@
-use std::collections::HashSet;
+use crate::compiler::{Column, Token};
 
-// Define the permitted ID characters
-pub fn permitted_id_characters() -> HashSet<char> {
-    ['_', '-'].iter().cloned().collect()
+// Define the permitted ID characters. `-` is not one of them: `a-b` is a
+// subtraction.
+pub const PERMITTED_ID_CHARACTERS: [u8; 1] = [b'_'];
+
+// Character classes of ASCII bytes, so the lexer can classify a byte with a
+// single table lookup. Bytes >= 0x80 belong to multi-byte UTF-8 characters
+// and have no class. Newlines are left out of WHITESPACE so runs of a class
+// never change the line number.
+pub const IDENT_START: u8 = 1;
+pub const IDENT_CONTINUE: u8 = 2;
+pub const DIGIT: u8 = 4;
+pub const WHITESPACE: u8 = 8;
+pub const NUMBER_CONTINUE: u8 = 16;
+
+pub static CHAR_CLASS: [u8; 256] = char_class_table();
+
+const fn char_class_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut byte = 0;
+    while byte < 128 {
+        let ch = byte as u8;
+        if ch.is_ascii_alphabetic() {
+            table[byte] = IDENT_START | IDENT_CONTINUE | NUMBER_CONTINUE;
+        } else if ch.is_ascii_digit() {
+            table[byte] = DIGIT | IDENT_CONTINUE | NUMBER_CONTINUE;
+        } else if ch == b' ' || ch == b'\t' || ch == b'\r' || ch == 0x0b || ch == 0x0c {
+            table[byte] = WHITESPACE;
+        }
+        byte += 1;
+    }
+    let mut index = 0;
+    while index < PERMITTED_ID_CHARACTERS.len() {
+        table[PERMITTED_ID_CHARACTERS[index] as usize] |= IDENT_CONTINUE;
+        index += 1;
+    }
+    table[b'_' as usize] |= IDENT_START | NUMBER_CONTINUE;
+    table
+}
+
+// Builds a keyword's token at its position
+pub type KeywordToken = fn(usize, Column) -> Token;
+
+// Keywords and the token each one produces
+const KEYWORDS: [(&str, KeywordToken); 43] = [
+    ("B1", Token::B1), ("B2", Token::B2), ("B4", Token::B4), ("B8", Token::B8),
+    ("B16", Token::B16), ("B32", Token::B32), ("B64", Token::B64), ("B128", Token::B128),
+    ("b1", Token::B1), ("b2", Token::B2), ("b4", Token::B4), ("b8", Token::B8),
+    ("b16", Token::B16), ("b32", Token::B32), ("b64", Token::B64), ("b128", Token::B128),
+    ("S8", Token::S8), ("S16", Token::S16), ("S32", Token::S32), ("S64", Token::S64), ("S128", Token::S128),
+    ("s8", Token::S8), ("s16", Token::S16), ("s32", Token::S32), ("s64", Token::S64), ("s128", Token::S128),
+    ("function", Token::Function), ("if", Token::If), ("else", Token::Else), ("while", Token::While),
+    ("return", Token::Return), ("break", Token::Break), ("continue", Token::Continue),
+    ("print", Token::Print), ("true", Token::True), ("false", Token::False), ("as", Token::As),
+    ("struct", Token::Struct), ("const", Token::Const),
+    ("for", Token::For), ("do", Token::Do), ("match", Token::Match), ("println", Token::Println),
+];
+
+// Keywords are found with a perfect hash: KEYWORD_HASH_SEED is picked so no
+// two keywords land in the same slot, and building the slot table fails to
+// compile if a new keyword collides, in which case another seed is needed.
+const KEYWORD_HASH_SEED: u32 = 47;
+const KEYWORD_SLOT_BITS: u32 = 9;
+
+static KEYWORD_SLOTS: [u8; 1 << KEYWORD_SLOT_BITS] = keyword_slots();
+
+// FNV-1a, keeping the top bits of the hash as the slot
+const fn keyword_hash(word: &[u8]) -> usize {
+    let mut hash = KEYWORD_HASH_SEED;
+    let mut index = 0;
+    while index < word.len() {
+        hash ^= word[index] as u32;
+        hash = hash.wrapping_mul(16777619);
+        index += 1;
+    }
+    (hash >> (32 - KEYWORD_SLOT_BITS)) as usize
+}
+
+const fn keyword_slots() -> [u8; 1 << KEYWORD_SLOT_BITS] {
+    let mut slots = [0u8; 1 << KEYWORD_SLOT_BITS];
+    let mut index = 0;
+    while index < KEYWORDS.len() {
+        let slot = keyword_hash(KEYWORDS[index].0.as_bytes());
+        if slots[slot] != 0 {
+            panic!("keyword hash collision, change KEYWORD_HASH_SEED");
+        }
+        slots[slot] = index as u8 + 1;
+        index += 1;
+    }
+    slots
+}
+
+pub fn keyword(word: &str) -> Option<KeywordToken> {
+    match KEYWORD_SLOTS[keyword_hash(word.as_bytes())] {
+        0 => None,
+        slot => {
+            let (keyword, token) = KEYWORDS[slot as usize - 1];
+            if keyword == word { Some(token) } else { None }
+        }
+    }
 }
@@ -0,0 +1,54 @@
+use crate::compiler::interner::Symbol;
+use crate::compiler::types::align_to;
+
+// Static memory holding the global variables, for backends that emit native
+// code. Each global gets an offset aligned for its type, laid out in
+// declaration order like the fields of a struct, and the section starts out
+// holding every initial value, little-endian, with zeroes for globals
+// declared without one. Constants are folded into the code that uses them
+// and take no space.
+pub struct DataSection {
+    pub globals: Vec<Global>,
+    pub bytes: Vec<u8>,
+    pub align: usize,
+}
+
+pub struct Global {
+    pub name: Symbol,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl DataSection {
+    pub fn new() -> Self {
+        DataSection { globals: Vec::new(), bytes: Vec::new(), align: 1 }
+    }
+
+    // Reserves zeroed space for `name` and returns its offset
+    pub fn add(&mut self, name: Symbol, size: usize, align: usize) -> usize {
+        let offset = align_to(self.bytes.len(), align);
+        self.bytes.resize(offset + size, 0);
+        self.align = self.align.max(align);
+        self.globals.push(Global { name, offset, size });
+        offset
+    }
+
+    // Stores the initial value of the integer at `offset`, `size` bytes wide.
+    // Values are kept normalized for their type, so the low bytes of the
+    // i128 are its two's complement encoding.
+    pub fn initialize(&mut self, offset: usize, size: usize, value: i128) {
+        self.bytes[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    // One line per global with its offset, size and initial bytes
+    pub fn dump(&self) -> String {
+        let mut out = format!("data: {} bytes, align {}\n", self.bytes.len(), self.align);
+        for global in self.globals.iter() {
+            let bytes: Vec<String> = self.bytes[global.offset..global.offset + global.size].iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            out.push_str(&format!("  {:>6} {:>4} {} = {}\n", global.offset, global.size, global.name, bytes.join(" ")));
+        }
+        out
+    }
+}
@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+// Interned string: identifiers and string literals are stored once and
+// referred to by index, so tokens are cheap to clone and comparing two names
+// is an integer comparison.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// Interned strings live until the program exits, which lets `as_str` hand
+// out plain `&'static str`s.
+struct Interner {
+    map: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        map: HashMap::new(),
+        strings: Vec::new(),
+    });
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(&symbol) = interner.map.get(string) {
+                return symbol;
+            }
+            let string: &'static str = Box::leak(string.to_string().into_boxed_str());
+            let symbol = Symbol(interner.strings.len() as u32);
+            interner.strings.push(string);
+            interner.map.insert(string, symbol);
+            symbol
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().strings[self.0 as usize])
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,944 @@
+use crate::compiler::TokenType;
+use crate::compiler::ast::{Ast, Binding, FormatPiece, FormatSpec, NodeId, NodeKind};
+use crate::compiler::interner::Symbol;
+use crate::compiler::prelude::{self, Builtin, Input, Intrinsic};
+use crate::compiler::types::{fold, Type};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+pub const DEFAULT_MAX_DEPTH: usize = 4096;
+
+// Native stack set aside for each call of the program, which runs through
+// several nested calls of the interpreter per statement and expression
+const STACK_PER_CALL: usize = 32 * 1024;
+
+// Calls shown at each end of a long call trace
+const TRACE_ENDS: usize = 8;
+
+// Stack the interpreter needs to reach `max_depth` nested calls, so deep
+// recursion ends in a stack overflow error of the program instead of
+// crashing the interpreter
+pub fn stack_size(max_depth: usize) -> usize {
+    max_depth.saturating_mul(STACK_PER_CALL).max(8 << 20)
+}
+
+// Runs a checked program by walking its syntax tree: the top-level
+// declarations first, in order, which initializes the globals, then `main`.
+// Values are i128s kept normalized for their type, the same representation
+// constant folding uses, so both agree on every result.
+pub struct Interpreter<'a> {
+    ast: &'a Ast,
+    functions: HashMap<Symbol, NodeId>,
+    // frames[0] holds the top-level variables, the last one those of the
+    // running function, each in the slot the parser gave it
+    frames: Vec<Vec<Variable>>,
+    // Elements of every array and fields of every struct, flattened.
+    // Variables refer to them by index, which is how array arguments share
+    // the caller's elements. Whatever a call allocates is freed when it
+    // returns, and a declaration that runs again, in a loop, takes back the
+    // storage it had.
+    storage: Vec<Vec<i128>>,
+    input: Input,
+    // Function and line of every call in progress, main first
+    calls: Vec<(Symbol, usize)>,
+    max_depth: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Variable {
+    // Slot whose declaration has not run yet
+    Unset,
+    Int(Type, i128),
+    // Element type and index into `storage`
+    Array(Type, usize),
+    // Index into `storage`
+    Struct(usize),
+}
+
+enum Flow {
+    Normal,
+    // Label of the targeted loop, the innermost one if None
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+    Return(Option<i128>),
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(ast: &'a Ast, max_depth: usize) -> Self {
+        Interpreter {
+            ast,
+            functions: HashMap::new(),
+            frames: vec![Vec::new()],
+            storage: Vec::new(),
+            input: Input::new(),
+            calls: Vec::new(),
+            max_depth,
+        }
+    }
+
+    // Returns the exit status, if `main` returns one
+    pub fn run(&mut self, program: NodeId) -> Option<i128> {
+        let ast = self.ast;
+        let body = match ast.get(program).kind {
+            NodeKind::Program(ref body) => body,
+            _ => panic!("Expected a program node"),
+        };
+        if let Flow::Return(_) = self.execute_block(body) {
+            panic!("Return outside of a function");
+        }
+        let main = Symbol::intern("main");
+        if !self.functions.contains_key(&main) {
+            panic!("Program has no main function");
+        }
+        self.call(main, &[], ast.get(program).line)
+    }
+
+    fn execute_block(&mut self, statements: &[NodeId]) -> Flow {
+        for &statement in statements {
+            match self.execute(statement) {
+                Flow::Normal => {}
+                flow => return flow,
+            }
+        }
+        Flow::Normal
+    }
+
+    fn execute(&mut self, id: NodeId) -> Flow {
+        let ast = self.ast;
+        let node = ast.get(id);
+        match node.kind {
+            NodeKind::FuncDecl { name, .. } => {
+                self.functions.insert(name, id);
+            }
+            NodeKind::VarDecl { tp, init, .. } => {
+                let value = init.map_or(0, |init| tp.truncate(self.evaluate(init)));
+                self.declare(id, Variable::Int(tp, value));
+            }
+            NodeKind::ConstDecl { tp, value, .. } => {
+                self.declare(id, Variable::Int(tp, value));
+            }
+            NodeKind::Declarations(ref declarations) => return self.execute_block(declarations),
+            NodeKind::ArrayDecl { tp, len, .. } => {
+                let array = self.zeroed(id, len);
+                self.declare(id, Variable::Array(tp, array));
+            }
+            NodeKind::StructDecl { .. } => {}
+            NodeKind::StructVarDecl { slots, .. } => {
+                let fields = self.zeroed(id, slots);
+                self.declare(id, Variable::Struct(fields));
+            }
+            NodeKind::Assign { target, value } => {
+                let value = self.evaluate(value);
+                match self.variable_mut(id) {
+                    &mut Variable::Int(tp, ref mut stored) => *stored = tp.truncate(value),
+                    _ => panic!("Cannot assign to '{}' as a whole", target),
+                }
+            }
+            NodeKind::FieldAssign { target, slot, tp, value, .. } => {
+                let fields = self.struct_storage(id, target, node.line);
+                let value = self.evaluate(value);
+                self.storage[fields][slot] = tp.truncate(value);
+            }
+            NodeKind::IndexAssign { target, index, value } => {
+                let (tp, array, index) = self.element(id, target, index, node.line);
+                let value = self.evaluate(value);
+                self.storage[array][index] = tp.truncate(value);
+            }
+            NodeKind::Call { callee, ref args } => {
+                self.call(callee, args, node.line);
+            }
+            NodeKind::If { condition, ref then_body, ref else_body } => {
+                return if self.evaluate(condition) != 0 {
+                    self.execute_block(then_body)
+                } else {
+                    self.execute_block(else_body)
+                };
+            }
+            NodeKind::While { condition, ref body, label } => {
+                while self.evaluate(condition) != 0 {
+                    if let Some(flow) = self.run_pass(body, label) {
+                        return flow;
+                    }
+                }
+            }
+            NodeKind::For { init, condition, step, ref body, label } => {
+                if let Some(init) = init {
+                    self.execute(init);
+                }
+                while condition.is_none_or(|condition| self.evaluate(condition) != 0) {
+                    if let Some(flow) = self.run_pass(body, label) {
+                        return flow;
+                    }
+                    if let Some(step) = step {
+                        self.execute(step);
+                    }
+                }
+            }
+            NodeKind::Match { scrutinee, ref arms, default, ref dispatch } => {
+                let key = ast.get(scrutinee).tp.unwrap().order_key(self.evaluate(scrutinee));
+                // the parser made sure some arm matches every value
+                let arm = dispatch.arm(key).or(default).unwrap();
+                return self.execute_block(ast.arm_body(arms[arm]));
+            }
+            NodeKind::DoWhile { ref body, condition, label } => loop {
+                if let Some(flow) = self.run_pass(body, label) {
+                    return flow;
+                }
+                if self.evaluate(condition) == 0 {
+                    break;
+                }
+            },
+            NodeKind::Print { ref pieces, ref args, newline, stderr, .. } => {
+                let mut args = args.iter();
+                let mut out = String::new();
+                for piece in pieces.iter() {
+                    match *piece {
+                        FormatPiece::Text(ref text) => out.push_str(text),
+                        FormatPiece::Argument(spec) => {
+                            let &arg = args.next().unwrap();
+                            out.push_str(&format_value(ast.get(arg).tp.unwrap(), self.evaluate(arg), spec));
+                        }
+                    }
+                }
+                if newline {
+                    out.push('\n');
+                }
+                if stderr {
+                    eprint!("{}", out);
+                } else {
+                    print!("{}", out);
+                }
+            }
+            NodeKind::Break(label) => return Flow::Break(label),
+            NodeKind::Continue(label) => return Flow::Continue(label),
+            NodeKind::Return(value) => return Flow::Return(value.map(|value| self.evaluate(value))),
+            _ => panic!("Unexpected statement {:?} at line {}", node.kind, node.line),
+        }
+        Flow::Normal
+    }
+
+    // Runs the body of a loop once. Returns None if the loop goes on, or the
+    // flow to carry on with once it ends: Normal after a `break` aimed at
+    // this loop, anything else is passed on to the loops around it.
+    fn run_pass(&mut self, body: &[NodeId], label: Option<Symbol>) -> Option<Flow> {
+        match self.execute_block(body) {
+            Flow::Normal | Flow::Continue(None) => None,
+            Flow::Continue(target) if target == label => None,
+            Flow::Break(None) => Some(Flow::Normal),
+            Flow::Break(target) if target == label => Some(Flow::Normal),
+            flow => Some(flow),
+        }
+    }
+
+    fn evaluate(&mut self, id: NodeId) -> i128 {
+        let ast = self.ast;
+        let node = ast.get(id);
+        match node.kind {
+            NodeKind::Number(value) => value as i128,
+            NodeKind::Bool(value) => value as i128,
+            NodeKind::Identifier(name) => match *self.variable_mut(id) {
+                Variable::Int(_, value) => value,
+                _ => panic!("'{}' is not an integer variable, line {}", name, node.line),
+            },
+            NodeKind::Index { array, index } => {
+                let (_, array, index) = self.element(id, array, index, node.line);
+                self.storage[array][index]
+            }
+            NodeKind::FieldAccess { base, slot, .. } => {
+                let fields = self.struct_storage(id, base, node.line);
+                self.storage[fields][slot]
+            }
+            NodeKind::Unary { op, operand } => {
+                let tp = node.tp.unwrap();
+                let value = self.evaluate(operand);
+                match op {
+                    TokenType::Not => (value == 0) as i128,
+                    TokenType::Tilde => tp.truncate(!value),
+                    _ => tp.truncate(value.wrapping_neg()),
+                }
+            }
+            NodeKind::Binary { op: TokenType::And, left, right } => {
+                (self.evaluate(left) != 0 && self.evaluate(right) != 0) as i128
+            }
+            NodeKind::Binary { op: TokenType::Or, left, right } => {
+                (self.evaluate(left) != 0 || self.evaluate(right) != 0) as i128
+            }
+            NodeKind::Binary { op, left, right } => {
+                let left_tp = ast.get(left).tp.unwrap();
+                let tp = match op {
+                    TokenType::ShiftLeft | TokenType::ShiftRight => left_tp,
+                    _ => Type::unify(left_tp, ast.get(right).tp.unwrap()).unwrap(),
+                };
+                let (l, r) = (self.evaluate(left), self.evaluate(right));
+                match fold(op, tp, l, r) {
+                    Some(value) => value,
+                    None => panic!("Division by zero at line {}", node.line),
+                }
+            }
+            NodeKind::Convert { tp, operand } => tp.truncate(self.evaluate(operand)),
+            NodeKind::Call { callee, ref args } => match self.call(callee, args, node.line) {
+                Some(value) => value,
+                None => panic!("'{}' did not return a value, line {}", callee, node.line),
+            },
+            _ => panic!("Unexpected expression {:?} at line {}", node.kind, node.line),
+        }
+    }
+
+    // Element type, array and checked index of `array[index]`, where `id` is
+    // the node naming the array
+    fn element(&mut self, id: NodeId, array: Symbol, index: NodeId, line: usize) -> (Type, usize, usize) {
+        let (tp, storage) = match *self.variable_mut(id) {
+            Variable::Array(tp, storage) => (tp, storage),
+            _ => panic!("'{}' is not an array, line {}", array, line),
+        };
+        let index_tp = self.ast.get(index).tp.unwrap();
+        let value = self.evaluate(index);
+        let len = self.storage[storage].len();
+        let in_bounds = match index_tp {
+            Type::Literal => value >= 0 && value < len as i128,
+            _ => (value as u128) < len as u128,
+        };
+        if !in_bounds {
+            panic!("Index {} out of bounds for array '{}' of length {} at line {}",
+                display(index_tp, value), array, len, line);
+        }
+        (tp, storage, value as usize)
+    }
+
+    fn call(&mut self, callee: Symbol, args: &[NodeId], line: usize) -> Option<i128> {
+        let ast = self.ast;
+        if let Some(intrinsic) = prelude::intrinsic(callee.as_str()) {
+            return self.call_intrinsic(intrinsic, args, line);
+        }
+        if self.calls.len() >= self.max_depth {
+            panic!("stack overflow in function {} at line {}, more than {} nested calls\n{}",
+                callee, line, self.max_depth, self.call_trace());
+        }
+        let function = match self.functions.get(&callee) {
+            Some(&function) => function,
+            None => panic!("Function '{}' called before its declaration ran, line {}", callee, line),
+        };
+        let (params, ret, body) = match ast.get(function).kind {
+            NodeKind::FuncDecl { ref params, ret, ref body, .. } => (params, ret, body),
+            _ => panic!("'{}' is not a function, line {}", callee, line),
+        };
+        let allocated = self.storage.len();
+        let mut frame = Vec::new();
+        for (&param, &arg) in params.iter().zip(args) {
+            let variable = match ast.get(param).kind {
+                NodeKind::Param { tp, .. } => {
+                    let value = self.evaluate(arg);
+                    Variable::Int(tp, tp.truncate(value))
+                }
+                NodeKind::ArrayParam { tp, .. } => {
+                    let array = match ast.get(arg).kind {
+                        NodeKind::Identifier(array) => match *self.variable_mut(arg) {
+                            Variable::Array(_, storage) => storage,
+                            _ => panic!("'{}' is not an array, line {}", array, line),
+                        },
+                        _ => panic!("Expected an array argument at line {}", line),
+                    };
+                    Variable::Array(tp, array)
+                }
+                // structs are passed by value
+                NodeKind::StructParam { .. } => {
+                    let fields = match ast.get(arg).kind {
+                        NodeKind::Identifier(base) => self.struct_storage(arg, base, line),
+                        _ => panic!("Expected a struct argument at line {}", line),
+                    };
+                    let copy = self.storage[fields].clone();
+                    Variable::Struct(self.allocate(copy))
+                }
+                _ => panic!("Unexpected parameter {:?}", ast.get(param).kind),
+            };
+            match ast.get(param).binding {
+                Some(Binding::Local(slot)) => bind(&mut frame, slot, variable),
+                binding => panic!("Parameter bound to {:?}", binding),
+            }
+        }
+        self.frames.push(frame);
+        self.calls.push((callee, line));
+        let flow = self.execute_block(body);
+        self.frames.pop();
+        self.calls.pop();
+        self.storage.truncate(allocated);
+        match flow {
+            Flow::Return(Some(value)) => ret.map(|ret| ret.truncate(value)),
+            _ => None,
+        }
+    }
+
+    // The calls in progress, innermost first. Runs of the same call are
+    // shown once, and only the ends of a long trace.
+    fn call_trace(&self) -> String {
+        let mut runs: Vec<(Symbol, usize, usize)> = Vec::new();
+        for (depth, &(callee, line)) in self.calls.iter().enumerate().rev() {
+            match runs.last_mut() {
+                Some(&mut (last, last_line, ref mut count)) if (last, last_line) == (callee, line) => *count += 1,
+                _ if depth == 0 => runs.push((callee, 0, 1)),
+                _ => runs.push((callee, line, 1)),
+            }
+        }
+        let mut trace = "call trace, innermost first:".to_string();
+        for (index, &(callee, line, count)) in runs.iter().enumerate() {
+            if index == TRACE_ENDS && runs.len() > 2 * TRACE_ENDS {
+                trace.push_str(&format!("\n  ... {} more", runs.len() - 2 * TRACE_ENDS));
+            }
+            if index >= TRACE_ENDS && index < runs.len().saturating_sub(TRACE_ENDS) {
+                continue;
+            }
+            trace.push_str(&format!("\n  in {}", callee));
+            if line != 0 {
+                trace.push_str(&format!(" called at line {}", line));
+            }
+            if count > 1 {
+                trace.push_str(&format!(" ({} times)", count));
+            }
+        }
+        trace
+    }
+
+    fn call_intrinsic(&mut self, intrinsic: &Intrinsic, args: &[NodeId], line: usize) -> Option<i128> {
+        match intrinsic.builtin {
+            Builtin::ReadInt => match self.input.read_int(intrinsic.ret.unwrap()) {
+                Ok(value) => Some(value),
+                Err(error) => panic!("read_int() at line {}: {}", line, error),
+            },
+            Builtin::ReadLine => {
+                let buffer = match self.ast.get(args[0]).kind {
+                    NodeKind::Identifier(name) => match *self.variable_mut(args[0]) {
+                        Variable::Array(_, storage) => storage,
+                        _ => panic!("'{}' is not an array, line {}", name, line),
+                    },
+                    _ => panic!("Expected an array argument at line {}", line),
+                };
+                let line = match self.input.read_line() {
+                    Some(line) => line,
+                    None => return Some(-1),
+                };
+                let stored = line.len().min(self.storage[buffer].len());
+                for (element, &byte) in self.storage[buffer].iter_mut().zip(&line) {
+                    *element = byte as i128;
+                }
+                Some(stored as i128)
+            }
+            Builtin::Eof => Some(self.input.at_eof() as i128),
+            Builtin::Exit => {
+                let status = self.evaluate(args[0]);
+                io::stdout().flush().unwrap();
+                std::process::exit(status as i32);
+            }
+            Builtin::Eprint | Builtin::Eprintln => unreachable!("{} is parsed as a print", intrinsic.name),
+        }
+    }
+
+    fn allocate(&mut self, values: Vec<i128>) -> usize {
+        self.storage.push(values);
+        self.storage.len() - 1
+    }
+
+    // Storage of `len` zeroes for the array or struct declared by `id`. Its
+    // slot still holds what the declaration allocated the last time it ran
+    // in this call, if it did.
+    fn zeroed(&mut self, id: NodeId, len: usize) -> usize {
+        let previous = match self.ast.get(id).binding {
+            Some(Binding::Global(slot)) => self.frames[0].get(slot).copied(),
+            Some(Binding::Local(slot)) => self.frames.last().unwrap().get(slot).copied(),
+            None => None,
+        };
+        match previous {
+            Some(Variable::Array(_, storage)) | Some(Variable::Struct(storage)) => {
+                self.storage[storage].fill(0);
+                storage
+            }
+            _ => self.allocate(vec![0; len]),
+        }
+    }
+
+    fn struct_storage(&mut self, id: NodeId, name: Symbol, line: usize) -> usize {
+        match *self.variable_mut(id) {
+            Variable::Struct(fields) => fields,
+            _ => panic!("'{}' is not a struct, line {}", name, line),
+        }
+    }
+
+    // Puts the variable declared by `id` in its slot
+    fn declare(&mut self, id: NodeId, variable: Variable) {
+        match self.ast.get(id).binding {
+            Some(Binding::Global(slot)) => bind(&mut self.frames[0], slot, variable),
+            Some(Binding::Local(slot)) => bind(self.frames.last_mut().unwrap(), slot, variable),
+            None => panic!("Declaration {:?} has no slot", self.ast.get(id).kind),
+        }
+    }
+
+    // The variable `id` declares or refers to, in the slot the parser
+    // resolved its name to
+    fn variable_mut(&mut self, id: NodeId) -> &mut Variable {
+        match self.ast.get(id).binding {
+            Some(Binding::Global(slot)) => &mut self.frames[0][slot],
+            Some(Binding::Local(slot)) => &mut self.frames.last_mut().unwrap()[slot],
+            None => panic!("{:?} refers to no variable", self.ast.get(id).kind),
+        }
+    }
+}
+
+// Slots are added as their declarations first run
+fn bind(frame: &mut Vec<Variable>, slot: usize, variable: Variable) {
+    if frame.len() <= slot {
+        frame.resize(slot + 1, Variable::Unset);
+    }
+    frame[slot] = variable;
+}
+
+fn format_value(tp: Type, value: i128, spec: FormatSpec) -> String {
+    let bits = tp.width().unwrap_or(128);
+    let pattern = value as u128 & (u128::MAX >> (128 - bits));
+    let digits = match spec.kind {
+        'x' => format!("{:x}", pattern),
+        'X' => format!("{:X}", pattern),
+        'o' => format!("{:o}", pattern),
+        'b' => format!("{:b}", pattern),
+        'c' => Some(value).filter(|&value| value >= 0 && value <= u32::MAX as i128)
+            .and_then(|value| char::from_u32(value as u32))
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+            .to_string(),
+        _ => display(tp, value),
+    };
+    let padding = spec.width.saturating_sub(digits.chars().count());
+    match (spec.zero, digits.starts_with('-')) {
+        (true, true) => format!("-{}{}", "0".repeat(padding), &digits[1..]),
+        (true, false) => format!("{}{}", "0".repeat(padding), digits),
+        (false, _) => format!("{}{}", " ".repeat(padding), digits),
+    }
+}
+
+// b128 values above i128::MAX are stored negative, so unsigned values are
+// printed through u128.
+fn display(tp: Type, value: i128) -> String {
+    match tp {
+        Type::Unsigned(_) => (value as u128).to_string(),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_value, Interpreter};
+    use crate::compiler::ast::FormatSpec;
+    use crate::compiler::types::Type;
+    use crate::compiler::{Lexer, Parser};
+    use std::panic::{self, AssertUnwindSafe};
+
+    // Exit status of the program, or the message the compiler or the
+    // program panicked with
+    fn run(source: &str, max_depth: usize) -> Result<Option<i128>, String> {
+        let tokens: Vec<_> = Lexer::new(source).map(|token| token.unwrap()).collect();
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut parser = Parser::new(&tokens);
+            let root = parser.parse();
+            Interpreter::new(parser.ast(), max_depth).run(root)
+        }))
+            .map_err(|payload| match payload.downcast::<String>() {
+                Ok(message) => *message,
+                Err(_) => "panicked without a message".to_string(),
+            })
+    }
+
+    const COUNTDOWN: &str = "
+function down(b64 n) -> b64 {
+    if (n == 0) {
+        return 0;
+    }
+    return down(n - 1) + 1;
+}
+
+function main() -> s32 {
+    return down(20) as s32;
+}
+";
+
+    #[test]
+    fn recursion_up_to_the_limit_runs() {
+        // main and down(20) to down(0)
+        assert_eq!(run(COUNTDOWN, 22), Ok(Some(20)));
+    }
+
+    #[test]
+    fn recursion_past_the_limit_overflows() {
+        let message = run(COUNTDOWN, 21).unwrap_err();
+        assert_eq!(message, "stack overflow in function down at line 6, more than 21 nested calls
+call trace, innermost first:
+  in down called at line 6 (19 times)
+  in down called at line 10
+  in main");
+    }
+
+    #[test]
+    fn long_call_trace_keeps_its_ends() {
+        let source = "
+function ping(b64 n) {
+    pong(n + 1);
+}
+
+function pong(b64 n) {
+    ping(n + 1);
+}
+
+function main() {
+    ping(0);
+}
+";
+        let message = run(source, 40).unwrap_err();
+        let trace: Vec<&str> = message.lines().skip(2).collect();
+        assert_eq!(trace.len(), 2 * super::TRACE_ENDS + 1);
+        assert_eq!(trace[0], "  in ping called at line 7");
+        assert_eq!(trace[1], "  in pong called at line 3");
+        assert_eq!(trace[super::TRACE_ENDS], "  ... 24 more");
+        assert_eq!(trace[2 * super::TRACE_ENDS - 1], "  in ping called at line 11");
+        assert_eq!(trace[2 * super::TRACE_ENDS], "  in main");
+    }
+
+    #[test]
+    fn not_binds_like_the_other_unary_operators() {
+        let source = "
+function main() -> b8 {
+    b8 x = 2;
+    b8 a = 3;
+    b8 b = 0;
+    b8 r = 0;
+    if (!x == 1) {
+        r = r + 100;
+    }
+    if (!x == 0) {
+        r = r + 10;
+    }
+    b1 one = 1 + !x;
+    return r + (a & !b) + one as b8;
+}
+";
+        assert_eq!(run(source, 8), Ok(Some(12)));
+    }
+
+    #[test]
+    fn eprint_is_a_prelude_function_not_a_keyword() {
+        let source = "
+function main() -> b8 {
+    eprint(\"checking \");
+    eprintln(\"{} of {}\", 1, 1);
+    b8 eprintln = 3;
+    return eprintln;
+}
+";
+        assert_eq!(run(source, 8), Ok(Some(3)));
+    }
+
+    #[test]
+    fn placeholders_pad_to_their_width() {
+        let spec = |kind: char, zero: bool, width: usize| FormatSpec { kind, zero, width };
+        assert_eq!(format_value(Type::Unsigned(8), 42, spec('d', false, 5)), "   42");
+        assert_eq!(format_value(Type::Unsigned(8), 42, spec('d', false, 1)), "42");
+        assert_eq!(format_value(Type::Signed(8), -42, spec('d', false, 5)), "  -42");
+        // zeroes go after the sign
+        assert_eq!(format_value(Type::Signed(8), -42, spec('d', true, 5)), "-0042");
+        assert_eq!(format_value(Type::Unsigned(128), -1, spec('d', false, 0)), u128::MAX.to_string());
+    }
+
+    #[test]
+    fn placeholders_show_the_bits_of_the_type() {
+        let spec = |kind: char, zero: bool, width: usize| FormatSpec { kind, zero, width };
+        assert_eq!(format_value(Type::Unsigned(16), 0xbeef, spec('x', false, 0)), "beef");
+        assert_eq!(format_value(Type::Unsigned(16), 0xbeef, spec('X', false, 6)), "  BEEF");
+        assert_eq!(format_value(Type::Signed(8), -1, spec('x', false, 0)), "ff");
+        assert_eq!(format_value(Type::Unsigned(8), 5, spec('b', true, 8)), "00000101");
+        assert_eq!(format_value(Type::Signed(8), -2, spec('b', false, 0)), "11111110");
+        assert_eq!(format_value(Type::Unsigned(8), 8, spec('o', false, 0)), "10");
+    }
+
+    #[test]
+    fn format_strings_take_one_argument_per_placeholder() {
+        assert_eq!(run("function main() { println(\"{} and {}\", 1); }", 8),
+            Err("Format string at line 1, column 37 has 2 placeholders but 1 arguments were given".to_string()));
+        assert_eq!(run("function main() { println(\"{{}}\", 1); }", 8),
+            Err("Format string at line 1, column 32 has 0 placeholders but 1 arguments were given".to_string()));
+        assert_eq!(run("function main() { println(\"{{{}}}\", 1); }", 8), Ok(None));
+    }
+
+    #[test]
+    fn character_placeholders_take_narrow_unsigned_values() {
+        let program = |arg: &str| format!("function main() {{ s64 s = 65; b128 w = 65; b32 n = 65; print(\"{{:c}}\", {}); }}", arg);
+        assert_eq!(run(&program("n"), 8), Ok(None));
+        assert_eq!(run(&program("65"), 8), Ok(None));
+        assert_eq!(run(&program("s"), 8), Err("Argument 1 of the format string at line 1, column 67 is a s64, \
+            but {:c} takes an unsigned value of at most 32 bits, convert it with `as b32`".to_string()));
+        assert_eq!(run(&program("w"), 8), Err("Argument 1 of the format string at line 1, column 67 is a b128, \
+            but {:c} takes an unsigned value of at most 32 bits, convert it with `as b32`".to_string()));
+        assert_eq!(run(&program("0xD800"), 8),
+            Err("Argument 1 of the format string at line 1, column 67 is 55296, not a character code point".to_string()));
+    }
+
+    #[test]
+    fn block_locals_go_out_of_scope() {
+        let after_if = "
+function main() {
+    b8 i = 0;
+    if (i == 1) {
+        b8 t = 1;
+    }
+    println(\"{}\", t);
+}
+";
+        assert_eq!(run(after_if, 8), Err("Identifier 't' not declared".to_string()));
+        let after_while = "
+function main() {
+    b8 i = 0;
+    while (i < 2) {
+        b8 t = i;
+        i = i + 1;
+    }
+    println(\"{}\", t);
+}
+";
+        assert_eq!(run(after_while, 8), Err("Identifier 't' not declared".to_string()));
+    }
+
+    #[test]
+    fn names_are_declared_once_per_scope() {
+        assert_eq!(run("function main() {\n    b8 x;\n    b8 x;\n}", 8),
+            Err("'x' at line 3 is already declared in this scope, at line 2".to_string()));
+        assert_eq!(run("function main() {\n    b8 a = 1, a = 2;\n}", 8),
+            Err("'a' at line 2 is already declared in this scope, at line 2".to_string()));
+        assert_eq!(run("function f(b8 n) {\n    b8 n = 1;\n}\nfunction main() {}", 8),
+            Err("'n' at line 2 is already declared in this scope, at line 1".to_string()));
+        // an inner block or loop body is a scope of its own
+        let shadowed = "
+function main() -> b8 {
+    b8 x = 1;
+    for (b8 i = 0; i < 2; i = i + 1) {
+        b8 i = 5;
+        x = x + i;
+    }
+    if (x == 11) {
+        b8 x = 2;
+        return x;
+    }
+    return x;
+}
+";
+        assert_eq!(run(shadowed, 8), Ok(Some(2)));
+    }
+
+    #[test]
+    fn endless_loops_assign_what_every_break_assigned() {
+        let program = |loop_head: &str| format!("
+function main() -> b8 {{
+    b8 x;
+    b8 y;
+    b8 i = 0;
+    {} {{
+        i = i + 1;
+        if (i == 3) {{
+            x = i;
+            break;
+        }}
+        y = i;
+    }}
+    return x;
+}}
+", loop_head);
+        assert_eq!(run(&program("while (true)"), 8), Ok(Some(3)));
+        assert_eq!(run(&program("for (;;)"), 8), Ok(Some(3)));
+        // the loop may end without running the body
+        assert_eq!(run(&program("while (i < 5)"), 8),
+            Err("Variable 'x' is read before it is assigned a value at line 14, column 12".to_string()));
+        // y is not assigned on the way to the break
+        assert_eq!(run(&program("while (true)").replace("return x;", "return y;"), 8),
+            Err("Variable 'y' is read before it is assigned a value at line 14, column 12".to_string()));
+        let labeled = "
+function main() -> b8 {
+    b8 x;
+    outer: while (true) {
+        while (true) {
+            x = 7;
+            break outer;
+        }
+    }
+    return x;
+}
+";
+        assert_eq!(run(labeled, 8), Ok(Some(7)));
+    }
+
+    #[test]
+    fn top_level_names_share_one_namespace() {
+        let main = "\nfunction main() {}";
+        assert_eq!(run(&format!("b8 soma;\nfunction soma() {{}}{}", main), 8),
+            Err("'soma' is declared more than once at top level, at lines 1 and 2".to_string()));
+        assert_eq!(run(&format!("function soma() {{}}\nconst b8 soma = 1;{}", main), 8),
+            Err("'soma' is declared more than once at top level, at lines 1 and 2".to_string()));
+        assert_eq!(run(&format!("struct Soma {{ b8 x; }}\nfunction Soma() {{}}{}", main), 8),
+            Err("'Soma' is declared more than once at top level, at lines 1 and 2".to_string()));
+        assert_eq!(run(&format!("struct Soma {{ b8 x; }}\nb8 Soma;{}", main), 8),
+            Err("'Soma' is declared more than once at top level, at lines 1 and 2".to_string()));
+        assert_eq!(run(&format!("b8 read_int;{}", main), 8),
+            Err("'read_int' is built in and cannot be declared again, line 1".to_string()));
+    }
+
+    #[test]
+    fn names_keep_the_variable_in_scope_where_they_are_written() {
+        // the second pass still reads the global, not the local declared
+        // after the read by the first pass
+        let source = "
+b8 g = 1;
+
+function main() -> b8 {
+    b8 i = 0;
+    b8 seen = 0;
+    while (i < 2) {
+        seen = seen + g;
+        b8 g = 5;
+        i = i + 1;
+    }
+    return seen;
+}
+";
+        assert_eq!(run(source, 8), Ok(Some(2)));
+    }
+
+    #[test]
+    fn declarations_in_a_loop_start_zeroed_every_pass() {
+        let source = "
+struct Point {
+    b8 x;
+}
+
+function main() -> b8 {
+    b8 total = 0;
+    for (b8 i = 0; i < 3; i = i + 1) {
+        b8[4] buffer;
+        Point p;
+        total = total + buffer[3] + p.x;
+        buffer[3] = 1;
+        p.x = 1;
+    }
+    return total;
+}
+";
+        assert_eq!(run(source, 8), Ok(Some(0)));
+    }
+
+    #[test]
+    fn negative_literals_do_not_fit_unsigned_types() {
+        let program = |decl: &str| format!("function main() {{ {} }}", decl);
+        assert_eq!(run(&program("b128 x = -1;"), 8),
+            Err("Literal -1 does not fit in b128 at line 1, column 26".to_string()));
+        assert_eq!(run(&program("b64 x = -1;"), 8),
+            Err("Literal -1 does not fit in b64 at line 1, column 25".to_string()));
+    }
+
+    #[test]
+    fn literals_shift_only_by_constant_amounts() {
+        assert_eq!(run("function main() { b8 x = 10; b8 y = 1 << x; }", 8),
+            Err("Literal 1 is shifted by an amount that is not constant at line 1, column 40, \
+                give it a width with a suffix like 1b32 or a cast".to_string()));
+        let source = "
+function main() -> b8 {
+    b8 x = 3;
+    b8 y = 1b8 << x;
+    b8 z = (1 << 3) + (1 as b8 << x);
+    return y + z;
+}
+";
+        assert_eq!(run(source, 8), Ok(Some(24)));
+    }
+
+    #[test]
+    fn match_arms_cover_every_value_once() {
+        let program = |tp: &str, arms: &str| format!("function main() {{\n    {} v = 0;\n    match (v) {{\n{}\n    }}\n}}", tp, arms);
+        assert_eq!(run(&program("b2", "        0 => {}\n        2..=3 => {}"), 8),
+            Err("Match on b2 at line 3 does not cover 1, add arms for it or a `_` arm".to_string()));
+        assert_eq!(run(&program("s8", "        -128..=-6 => {}\n        0..=127 => {}"), 8),
+            Err("Match on s8 at line 3 does not cover -5..=-1, add arms for it or a `_` arm".to_string()));
+        assert_eq!(run(&program("b8", "        0..=5 => {}\n        9 | 3 => {}\n        _ => {}"), 8),
+            Err("Pattern 3 at line 5 overlaps a pattern of the arm at line 4".to_string()));
+        assert_eq!(run(&program("b8", "        _ => {}\n        0 => {}"), 8),
+            Err("Arm at line 5 is unreachable, the `_` arm at line 4 matches every value left".to_string()));
+        assert_eq!(run(&program("b128", "        0..=340282366920938463463374607431768211454 => {}"), 8),
+            Err("Match on b128 at line 3 does not cover 340282366920938463463374607431768211455, \
+                add arms for it or a `_` arm".to_string()));
+    }
+
+    #[test]
+    fn match_picks_the_arm_of_the_value() {
+        let signed = "
+function sign(s8 v) -> b8 {
+    match (v) {
+        -128..=-1 => { return 1; }
+        0 => { return 2; }
+        1..=127 => { return 3; }
+    }
+}
+
+function main() -> b8 {
+    return sign(-128) * 100 + sign(0) * 10 + sign(127);
+}
+";
+        assert_eq!(run(signed, 8), Ok(Some(123)));
+        let wide = "
+function half(b128 v) -> b8 {
+    match (v) {
+        0..=170141183460469231731687303715884105727 => { return 1; }
+        170141183460469231731687303715884105728..=340282366920938463463374607431768211455 => { return 2; }
+    }
+}
+
+function main() -> b8 {
+    return half(0) * 100 + half(170141183460469231731687303715884105728) * 10 + half(340282366920938463463374607431768211455);
+}
+";
+        assert_eq!(run(wide, 8), Ok(Some(122)));
+        // searched ranges for 3 of 8 values and for a span of 2001 values, a
+        // table for 7 of 8
+        let dispatch = |arms: &str| format!("
+function pick(b16 v) -> b8 {{
+    match (v) {{
+        {}
+        _ => {{ return 9; }}
+    }}
+}}
+
+function main() -> b8 {{
+    return pick(0) * 100 + pick(1) * 10 + pick(7);
+}}
+", arms);
+        assert_eq!(run(&dispatch("0..=1 => { return 1; } 7 => { return 2; }"), 8), Ok(Some(112)));
+        assert_eq!(run(&dispatch("0 => { return 1; } 7..=2000 => { return 2; }"), 8), Ok(Some(192)));
+        assert_eq!(run(&dispatch("0 => { return 1; } 2..=7 => { return 2; }"), 8), Ok(Some(192)));
+    }
+
+    #[test]
+    fn constant_expressions_do_not_wrap() {
+        assert_eq!(run("const b8 A = 200;\nconst b8 B = A + 100;\nfunction main() {}", 8),
+            Err("Constant expression overflows b8 at line 2, column 16".to_string()));
+        assert_eq!(run("const s8 A = -128;\nconst s8 B = -A;\nfunction main() {}", 8),
+            Err("Constant expression overflows s8 at line 2, column 14".to_string()));
+        assert_eq!(run("const b8 A = 1;\nconst b8 B = A - 2;\nfunction main() {}", 8),
+            Err("Constant expression overflows b8 at line 2, column 16".to_string()));
+        assert_eq!(run("const b8 A = 200;\nconst b8 B = A + 55;\nfunction main() -> b8 { return B; }", 8),
+            Ok(Some(255)));
+    }
+
+    #[test]
+    fn suffixed_minimums_fit_once_negated() {
+        let source = "
+function main() -> b8 {
+    s8 low = -128s8;
+    s16 wide = -128s8 as s16;
+    s128 lowest = -170141183460469231731687303715884105728;
+    return (low as b8) + (wide == -128) as b8 + (lowest < 0) as b8;
+}
+";
+        assert_eq!(run(source, 8), Ok(Some(130)));
+        assert_eq!(run("function main() { s8 x = -129s8; }", 8),
+            Err("Literal 129 does not fit in s8 at line 1, column 31".to_string()));
+    }
+}
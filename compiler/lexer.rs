@@ -1,249 +1,759 @@
-use crate::compiler::Token;
-use crate::compiler::constants::permitted_id_characters;
-use std::iter::Peekable;
-use std::str::Chars;
+use crate::compiler::{Column, Token, TokenType};
+use crate::compiler::interner::Symbol;
+use crate::compiler::constants::{keyword, CHAR_CLASS, DIGIT, IDENT_CONTINUE, IDENT_START, NUMBER_CONTINUE, WHITESPACE};
+use crate::compiler::unicode::{is_xid_continue, is_xid_start, nfc};
+use std::collections::VecDeque;
+use std::fmt;
+use std::num::IntErrorKind;
 
+// `column` counts characters and `byte_column` UTF-8 bytes from the start of
+// the line, so the two differ once the line has non-ASCII text before the
+// error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub byte_column: usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {} (byte {})", self.message, self.line, self.column, self.byte_column)
+    }
+}
+
+// Source files must be UTF-8 without a byte order mark.
+pub fn decode_source(bytes: &[u8]) -> Result<&str, LexError> {
+    let message = if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some("Source starts with a UTF-8 byte order mark, save it without one")
+    } else if bytes.starts_with(&[0xFE, 0xFF]) || bytes.starts_with(&[0xFF, 0xFE]) {
+        Some("Source is UTF-16 encoded, save it as UTF-8")
+    } else {
+        None
+    };
+    if let Some(message) = message {
+        return Err(LexError { message: message.to_string(), line: 1, column: 1, byte_column: 1 });
+    }
+    std::str::from_utf8(bytes).map_err(|error| {
+        let valid = &bytes[..error.valid_up_to()];
+        let line_start = valid.iter().rposition(|&byte| byte == b'\n').map_or(0, |index| index + 1);
+        let line = std::str::from_utf8(&valid[line_start..]).unwrap();
+        LexError {
+            message: "Invalid UTF-8".to_string(),
+            line: valid.iter().filter(|&&byte| byte == b'\n').count() + 1,
+            column: line.chars().count() + 1,
+            byte_column: line.len() + 1,
+        }
+    })
+}
+
+// Tokens are produced through `Iterator`, ending with `Token::Eof`, or with
+// the first error. In lossless mode whitespace and comments are produced too,
+// as `Token::Whitespace` and `Token::Comment`, and concatenating `lexeme()`
+// after every token gives back the source byte for byte.
 pub struct Lexer<'a> {
-    input: Peekable<Chars<'a>>,
+    source: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
     current_line: usize,
     current_column: usize,
+    line_start: usize,
+    lossless: bool,
+    finished: bool,
+    // Tokens produced by peeking, not yet returned by `next`, with their
+    // spans
+    lookahead: VecDeque<(Result<Token, LexError>, usize, usize)>,
+    last_span: (usize, usize),
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Lexer {
-            input: input.chars().peekable(),
+            source: input,
+            bytes: input.as_bytes(),
+            pos: 0,
             current_line: 1,
             current_column: 0,
+            line_start: 0,
+            lossless: false,
+            finished: false,
+            lookahead: VecDeque::new(),
+            last_span: (0, 0),
         }
     }
 
-    pub fn next_token(&mut self) -> Token {
-        while let Some(&ch) = self.input.peek() {
-            return match ch {
-                ' ' | '\t' => {
-                    self.consume_whitespace();
-                    continue;
-                }
-                '\n' => {
-                    self.consume_newline();
-                    continue;
-                }
-                'a'..='z' | 'A'..='Z' | '_' => {
-                    return self.lex_identifier_or_keyword();
-                }
-                '0'..='9' => {
-                    return self.lex_number();
+    pub fn with_trivia(input: &'a str) -> Self {
+        let mut lexer = Lexer::new(input);
+        lexer.lossless = true;
+        lexer
+    }
+
+    // Looks `n` tokens ahead without consuming anything, `peek_nth(0)` being
+    // the token the next call to `next` returns.
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Result<Token, LexError>> {
+        while self.lookahead.len() <= n {
+            match self.produce() {
+                Some(item) => self.lookahead.push_back(item),
+                None => break,
+            }
+        }
+        self.lookahead.get(n).map(|(item, _, _)| item)
+    }
+
+    pub fn peek(&mut self) -> Option<&Result<Token, LexError>> {
+        self.peek_nth(0)
+    }
+
+    // Source text of the token last returned by `next`.
+    pub fn lexeme(&self) -> &'a str {
+        &self.source[self.last_span.0..self.last_span.1]
+    }
+
+    fn produce(&mut self) -> Option<(Result<Token, LexError>, usize, usize)> {
+        if self.finished {
+            return None;
+        }
+        let mut start = self.pos;
+        let item = loop {
+            let item = self.next_token();
+            match item {
+                Ok(Token::Whitespace(_, _, _)) | Ok(Token::Comment(_, _, _)) if !self.lossless => {
+                    start = self.pos;
                 }
-                '"' => return self.lex_string(),
-                '=' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    if self.input.peek() == Some(&'=') {
-                        self.input.next();
-                        self.current_column += 1;
-                        Token::Equal(self.current_line, self.current_column)
-                    } else {
-                        Token::Assing(self.current_line, self.current_column)
-                    }
+                _ => break item,
+            }
+        };
+        match item {
+            Ok(Token::Eof(_, _)) | Err(_) => self.finished = true,
+            _ => {}
+        }
+        Some((item, start, self.pos))
+    }
+
+    fn peek_byte(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        match self.peek_byte() {
+            Some(byte) if byte < 0x80 => Some(byte as char),
+            Some(_) => self.source[self.pos..].chars().next(),
+            None => None,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek_char()?;
+        self.pos += ch.len_utf8();
+        if ch == '\n' {
+            self.current_line += 1;
+            self.current_column = 0;
+            self.line_start = self.pos;
+        } else {
+            self.current_column += 1;
+        }
+        Some(ch)
+    }
+
+    // Advances over the ASCII bytes of the given class, which never include a
+    // newline, in one go.
+    fn skip_class(&mut self, class: u8) {
+        let start = self.pos;
+        while self.pos < self.bytes.len() && CHAR_CLASS[self.bytes[self.pos] as usize] & class != 0 {
+            self.pos += 1;
+        }
+        self.current_column += self.pos - start;
+    }
+
+    // Whitespace and comments are dropped unless the lexer is lossless, so
+    // their text is only copied then
+    fn trivia_text(&self, start: usize) -> String {
+        if self.lossless {
+            self.source[start..self.pos].to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    fn byte_column(&self) -> usize {
+        self.pos - self.line_start
+    }
+
+    fn column(&self) -> Column {
+        Column { chars: self.current_column, bytes: self.byte_column() }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, LexError> {
+        Err(LexError { message, line: self.current_line, column: self.current_column, byte_column: self.byte_column() })
+    }
+
+    fn next_token(&mut self) -> Result<Token, LexError> {
+        let start = self.pos;
+        let ch = match self.peek_char() {
+            Some(ch) => ch,
+            None => return Ok(Token::Eof(self.current_line, self.column())),
+        };
+        Ok(match ch {
+            _ if ch == '\n' || ch.is_ascii() && CHAR_CLASS[ch as usize] & WHITESPACE != 0 => {
+                self.consume_whitespace();
+                Token::Whitespace(self.trivia_text(start), self.current_line, self.column())
+            }
+            _ if ch.is_ascii() && CHAR_CLASS[ch as usize] & IDENT_START != 0 => {
+                return Ok(self.lex_identifier_or_keyword());
+            }
+            _ if !ch.is_ascii() && is_xid_start(ch) => {
+                return Ok(self.lex_identifier_or_keyword());
+            }
+            _ if ch.is_ascii() && CHAR_CLASS[ch as usize] & DIGIT != 0 => {
+                return self.lex_number();
+            }
+            '"' => return self.lex_string(),
+            '=' => {
+                self.bump();
+                if self.peek_byte() == Some(b'=') {
+                    self.bump();
+                    Token::Equal(self.current_line, self.column())
+                } else if self.peek_byte() == Some(b'>') {
+                    self.bump();
+                    Token::FatArrow(self.current_line, self.column())
+                } else {
+                    Token::Assing(self.current_line, self.column())
                 }
-                '!' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    if self.input.peek() == Some(&'=') {
-                        self.input.next();
-                        self.current_column += 1;
-                        Token::NotEqual(self.current_line, self.current_column)
-                    } else {
-                        panic!("Unexpected character after !, line {}", self.current_line);
-                    }
+            }
+            '!' => {
+                self.bump();
+                if self.peek_byte() == Some(b'=') {
+                    self.bump();
+                    Token::NotEqual(self.current_line, self.column())
+                } else {
+                    Token::Not(self.current_line, self.column())
                 }
-                '>' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    if self.input.peek() == Some(&'=') {
-                        self.input.next();
-                        self.current_column += 1;
-                        Token::GreaterEqual(self.current_line, self.current_column)
-                    } else {
-                        Token::Greater(self.current_line, self.current_column)
-                    }
+            }
+            '&' => {
+                self.bump();
+                if self.peek_byte() == Some(b'&') {
+                    self.bump();
+                    Token::And(self.current_line, self.column())
+                } else {
+                    Token::Ampersand(self.current_line, self.column())
                 }
-                '<' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    if self.input.peek() == Some(&'=') {
-                        self.input.next();
-                        self.current_column += 1;
-                        Token::LessEqual(self.current_line, self.current_column)
-                    } else {
-                        Token::Less(self.current_line, self.current_column)
-                    }
+            }
+            '|' => {
+                self.bump();
+                if self.peek_byte() == Some(b'|') {
+                    self.bump();
+                    Token::Or(self.current_line, self.column())
+                } else {
+                    Token::Pipe(self.current_line, self.column())
                 }
-                '+' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::Plus(self.current_line, self.current_column)
+            }
+            '^' => {
+                self.bump();
+                Token::Caret(self.current_line, self.column())
+            }
+            '~' => {
+                self.bump();
+                Token::Tilde(self.current_line, self.column())
+            }
+            '%' => {
+                self.bump();
+                Token::Percent(self.current_line, self.column())
+            }
+            '>' => {
+                self.bump();
+                if self.peek_byte() == Some(b'=') {
+                    self.bump();
+                    Token::GreaterEqual(self.current_line, self.column())
+                } else if self.peek_byte() == Some(b'>') {
+                    self.bump();
+                    Token::ShiftRight(self.current_line, self.column())
+                } else {
+                    Token::Greater(self.current_line, self.column())
                 }
-                '-' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::Minus(self.current_line, self.current_column)
+            }
+            '<' => {
+                self.bump();
+                if self.peek_byte() == Some(b'=') {
+                    self.bump();
+                    Token::LessEqual(self.current_line, self.column())
+                } else if self.peek_byte() == Some(b'<') {
+                    self.bump();
+                    Token::ShiftLeft(self.current_line, self.column())
+                } else {
+                    Token::Less(self.current_line, self.column())
                 }
-                '*' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::Star(self.current_line, self.current_column)
+            }
+            '+' => {
+                self.bump();
+                Token::Plus(self.current_line, self.column())
+            }
+            '-' => {
+                self.bump();
+                if self.peek_byte() == Some(b'>') {
+                    self.bump();
+                    Token::Arrow(self.current_line, self.column())
+                } else {
+                    Token::Minus(self.current_line, self.column())
                 }
-                '/' => {
-                    if self.input.peek() == Some(&'/'){
+            }
+            '*' => {
+                self.bump();
+                Token::Star(self.current_line, self.column())
+            }
+            // The `/` is consumed before looking at what follows it: a second
+            // `/` or a `*` starts a comment, anything else makes it a division.
+            '/' => {
+                self.bump();
+                match self.peek_byte() {
+                    Some(b'/') => {
+                        // `///` starts a doc comment, `////` is a plain one
+                        let lookahead = &self.bytes[self.pos..];
+                        if lookahead.get(1) == Some(&b'/') && lookahead.get(2) != Some(&b'/') {
+                            return Ok(self.lex_doc_comment());
+                        }
                         self.consume_until_newline();
-                        continue;
+                        Token::Comment(self.trivia_text(start), self.current_line, self.column())
                     }
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::Slash(self.current_line, self.current_column)
-                }
-                '(' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::LeftParenthesis(self.current_line, self.current_column)
-                }
-                ')' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::RightParenthesis(self.current_line, self.current_column)
-                }
-                '{' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::LeftBraces(self.current_line, self.current_column)
+                    Some(b'*') => {
+                        self.consume_block_comment()?;
+                        Token::Comment(self.trivia_text(start), self.current_line, self.column())
                     }
-                '}' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::RightBraces(self.current_line, self.current_column)
+                    _ => Token::Slash(self.current_line, self.column()),
                 }
-                ';' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::Semicolon(self.current_line, self.current_column)
-                }
-                ',' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    Token::Comma(self.current_line, self.current_column)
+            }
+            '(' => {
+                self.bump();
+                Token::LeftParenthesis(self.current_line, self.column())
+            }
+            ')' => {
+                self.bump();
+                Token::RightParenthesis(self.current_line, self.column())
+            }
+            '{' => {
+                self.bump();
+                Token::LeftBraces(self.current_line, self.column())
+            }
+            '}' => {
+                self.bump();
+                Token::RightBraces(self.current_line, self.column())
+            }
+            '[' => {
+                self.bump();
+                Token::LeftBracket(self.current_line, self.column())
+            }
+            ']' => {
+                self.bump();
+                Token::RightBracket(self.current_line, self.column())
+            }
+            ';' => {
+                self.bump();
+                Token::Semicolon(self.current_line, self.column())
+            }
+            ',' => {
+                self.bump();
+                Token::Comma(self.current_line, self.column())
+            }
+            '.' => {
+                self.bump();
+                if self.bytes[self.pos..].starts_with(b".=") {
+                    self.bump();
+                    self.bump();
+                    Token::DotDotEq(self.current_line, self.column())
+                } else {
+                    Token::Dot(self.current_line, self.column())
                 }
-                _ => panic!("Unexpected character: {}, line {}", ch, self.current_line),
-            };
-        }
-        Token::EOF(self.current_line, self.current_column)
+            }
+            ':' => {
+                self.bump();
+                Token::Colon(self.current_line, self.column())
+            }
+            _ => {
+                self.bump();
+                return self.error(format!("Unexpected character: {} (U+{:04X})", ch, ch as u32));
+            }
+        })
     }
 
+    // Stops before the newline, which belongs to the following whitespace.
     fn consume_until_newline(&mut self) {
-        while let Some(ch) = self.input.peek() {
-            if ch != &'\n' {
-                self.input.next();
-                self.current_column += 1;
-            } else {
-                self.consume_newline();
-                return
+        while let Some(ch) = self.peek_char() {
+            if ch == '\n' {
+                break;
             }
+            self.bump();
         }
     }
 
-    fn consume_whitespace(&mut self) {
-        while let Some(ch) = self.input.peek() {
-            if ch.is_whitespace() {
-                self.input.next();
-                self.current_column += 1;
-            } else {
+    // Block comments nest, so a commented out region may itself contain
+    // /* ... */ comments.
+    fn consume_block_comment(&mut self) -> Result<(), LexError> {
+        let unterminated = LexError {
+            message: "Unterminated block comment".to_string(),
+            line: self.current_line,
+            column: self.current_column,
+            byte_column: self.byte_column(),
+        };
+        self.bump(); // consume '*'
+        let mut depth = 1;
+        while depth > 0 {
+            match self.bump() {
+                Some('/') if self.peek_byte() == Some(b'*') => {
+                    self.bump();
+                    depth += 1;
+                }
+                Some('*') if self.peek_byte() == Some(b'/') => {
+                    self.bump();
+                    depth -= 1;
+                }
+                Some(_) => {}
+                None => return Err(unterminated),
+            }
+        }
+        Ok(())
+    }
+
+    fn lex_doc_comment(&mut self) -> Token {
+        self.bump(); // consume second '/'
+        self.bump(); // consume third '/'
+        if self.peek_byte() == Some(b' ') {
+            self.bump();
+        }
+        let mut text = String::new();
+        while let Some(ch) = self.peek_char() {
+            if ch == '\n' {
                 break;
             }
+            self.bump();
+            text.push(ch);
         }
+        Token::DocComment(text.trim_end().to_string(), self.current_line, self.column())
     }
 
-    fn consume_newline(&mut self) {
-        self.input.next();
-        self.current_line += 1;
-        self.current_column = 0;
+    fn consume_whitespace(&mut self) {
+        loop {
+            self.skip_class(WHITESPACE);
+            match self.peek_char() {
+                Some('\n') => {
+                    self.bump();
+                }
+                Some(ch) if ch.is_whitespace() => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
     }
 
+    // Keywords are all ASCII, so only identifiers with other characters need
+    // normalizing.
     fn lex_identifier_or_keyword(&mut self) -> Token {
-        let mut word = String::new();
-        while let Some(&ch) = self.input.peek() {
-            if ch.is_alphanumeric() || permitted_id_characters().contains(&ch) {
-                self.input.next();
-                self.current_column += 1;
-                word.push(ch);
-            } else {
-                break;
+        let start = self.pos;
+        let mut ascii = true;
+        loop {
+            self.skip_class(IDENT_CONTINUE);
+            match self.peek_char() {
+                Some(ch) if !ch.is_ascii() && (is_xid_continue(ch) || self.pos == start) => {
+                    ascii = false;
+                    self.bump();
+                }
+                _ => break,
             }
         }
 
-        match word.as_str() {
-            "B1" => Token::B1(self.current_line, self.current_column),
-            "B2" => Token::B2(self.current_line, self.current_column),
-            "B4" => Token::B4(self.current_line, self.current_column),
-            "B8" => Token::B8(self.current_line, self.current_column),
-            "B16" => Token::B16(self.current_line, self.current_column),
-            "B32" => Token::B32(self.current_line, self.current_column),
-            "B64" => Token::B64(self.current_line, self.current_column),
-            "B128" => Token::B128(self.current_line, self.current_column),
-            "b1" => Token::B1(self.current_line, self.current_column),
-            "b2" => Token::B2(self.current_line, self.current_column),
-            "b4" => Token::B4(self.current_line, self.current_column),
-            "b8" => Token::B8(self.current_line, self.current_column),
-            "b16" => Token::B16(self.current_line, self.current_column),
-            "b32" => Token::B32(self.current_line, self.current_column),
-            "b64" => Token::B64(self.current_line, self.current_column),
-            "b128" => Token::B128(self.current_line, self.current_column),
-            "function" => Token::Function(self.current_line, self.current_column),
-            "if" => Token::If(self.current_line, self.current_column),
-            "else" => Token::Else(self.current_line, self.current_column),
-            "while" => Token::While(self.current_line, self.current_column),
-            "return" => Token::Return(self.current_line, self.current_column),
-            "break" => Token::Break(self.current_line, self.current_column),
-            "continue" => Token::Continue(self.current_line, self.current_column),
-            "print" => Token::Print(self.current_line, self.current_column),
-            "true" => Token::True(self.current_line, self.current_column),
-            "false" => Token::False(self.current_line, self.current_column),
-            _ => Token::Identifier(word, self.current_line, self.current_column),
-        }
-    }
-    
-    fn lex_string(&mut self) -> Token {
-        self.input.next();
-        self.current_column += 1;
+        let word = &self.source[start..self.pos];
+        if !ascii {
+            return Token::Identifier(Symbol::intern(&nfc(word)), self.current_line, self.column());
+        }
+        match keyword(word) {
+            Some(token) => token(self.current_line, self.column()),
+            None => Token::Identifier(Symbol::intern(word), self.current_line, self.column()),
+        }
+    }
+
+    // Strings may span several lines. A `\` at the end of a line joins it with
+    // the next one, skipping the next line's leading whitespace.
+    fn lex_string(&mut self) -> Result<Token, LexError> {
+        self.bump();
+        let unterminated = LexError {
+            message: "Unterminated string".to_string(),
+            line: self.current_line,
+            column: self.current_column,
+            byte_column: self.byte_column(),
+        };
         let mut string = String::new();
-        while let Some(&ch) = self.input.peek() {
-            match ch {
-                '"' => {
-                    self.input.next();
-                    self.current_column += 1;
-                    break;
-                }
-                _ => {
-                    string.push(ch);
-                    self.input.next();
-                    self.current_column += 1;
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => {
+                    if let Some(ch) = self.lex_escape(&unterminated)? {
+                        string.push(ch);
+                    }
                 }
+                Some(ch) => string.push(ch),
+                None => return Err(unterminated),
             }
         }
-        Token::StringLiteral(string, self.current_line, self.current_column)
+        Ok(Token::StringLiteral(Symbol::intern(&string), self.current_line, self.column()))
     }
 
-    fn lex_number(&mut self) -> Token {
-        let mut word = String::new();
-        while let Some(&ch) = self.input.peek() {
-            if ch.is_digit(10) {
-                self.input.next();
-                self.current_column += 1;
-                word.push(ch);
-            } else {
-                break;
+    // Escape sequences after a `\`: \n \t \r \0 \\ \" \xNN and \u{NNNN}.
+    // Returns None for a line continuation.
+    fn lex_escape(&mut self, unterminated: &LexError) -> Result<Option<char>, LexError> {
+        let ch = match self.bump() {
+            Some(ch) => ch,
+            None => return Err(unterminated.clone()),
+        };
+        Ok(match ch {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '\n' => {
+                self.skip_class(WHITESPACE);
+                None
+            }
+            'x' => {
+                let mut digits = String::new();
+                for _ in 0..2 {
+                    match self.peek_char() {
+                        Some(digit) if digit.is_ascii_hexdigit() => {
+                            self.bump();
+                            digits.push(digit);
+                        }
+                        _ => return self.error("Expected two hex digits after \\x".to_string()),
+                    }
+                }
+                let value = u8::from_str_radix(&digits, 16).unwrap();
+                if value > 0x7F {
+                    return self.error(format!("Escape \\x{} is out of the ASCII range", digits));
+                }
+                Some(value as char)
+            }
+            'u' => {
+                if self.bump() != Some('{') {
+                    return self.error("Expected { after \\u".to_string());
+                }
+                let mut digits = String::new();
+                loop {
+                    match self.bump() {
+                        Some('}') => break,
+                        Some(digit) if digit.is_ascii_hexdigit() && digits.len() < 6 => digits.push(digit),
+                        _ => return self.error("Invalid \\u{...} escape".to_string()),
+                    }
+                }
+                match u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32) {
+                    Some(ch) => Some(ch),
+                    None => return self.error(format!("Invalid unicode escape \\u{{{}}}", digits)),
+                }
+            }
+            _ => return self.error(format!("Unknown escape sequence \\{}", ch)),
+        })
+    }
+
+    // Numbers are decimal unless prefixed with 0x, 0b or 0o, may use `_` as a
+    // digit separator and may end in a width suffix: 255b8, 0b1010_b4.
+    // Hexadecimal digits include `b`, so there the suffix must follow a `_`.
+    // A 0 with a width suffix, 0b8, reads as that rather than as a binary
+    // number with a digit out of range; only 0b1 is both, and is binary.
+    fn lex_number(&mut self) -> Result<Token, LexError> {
+        let start = self.pos;
+        self.skip_class(NUMBER_CONTINUE);
+        let word = &self.source[start..self.pos];
+
+        let (radix, digits) = match word.get(..2) {
+            Some("0b") if word != "0b1" && width_suffix(&word[1..]).is_some() => (10, word),
+            Some("0x") | Some("0X") => (16, &word[2..]),
+            Some("0b") | Some("0B") => (2, &word[2..]),
+            Some("0o") | Some("0O") => (8, &word[2..]),
+            _ => (10, word),
+        };
+        let suffix_start = if radix == 16 {
+            digits.rfind('_').map(|index| index + 1).filter(|&index| {
+                let rest = &digits[index..];
+                (rest.starts_with('b') || rest.starts_with('s'))
+                    && rest.len() > 1 && rest[1..].chars().all(|ch| ch.is_ascii_digit())
+            })
+        } else {
+            digits.find(['b', 's'])
+        };
+        let (digits, suffix) = match suffix_start {
+            Some(index) => (&digits[..index], Some(&digits[index..])),
+            None => (digits, None),
+        };
+
+        let suffix = match suffix {
+            Some(suffix) => match width_suffix(suffix) {
+                Some(kind) => Some(kind),
+                None => return self.error(format!("Invalid suffix '{}' in numeric literal {}", suffix, word)),
+            },
+            None => None,
+        };
+        let digits: String = digits.chars().filter(|&ch| ch != '_').collect();
+        if digits.is_empty() {
+            return self.error(format!("Numeric literal {} has no digits", word));
+        }
+        let value = match u128::from_str_radix(&digits, radix) {
+            Ok(value) => value,
+            Err(ref error) if *error.kind() == IntErrorKind::PosOverflow => {
+                return self.error(format!("Numeric literal {} does not fit in 128 bits", word));
             }
+            Err(_) => return self.error(format!("Invalid digit in numeric literal {}", word)),
+        };
+        Ok(Token::Number(value, suffix, self.current_line, self.column()))
+    }
+}
+
+fn width_suffix(suffix: &str) -> Option<TokenType> {
+    match suffix {
+        "b1" => Some(TokenType::B1),
+        "b2" => Some(TokenType::B2),
+        "b4" => Some(TokenType::B4),
+        "b8" => Some(TokenType::B8),
+        "b16" => Some(TokenType::B16),
+        "b32" => Some(TokenType::B32),
+        "b64" => Some(TokenType::B64),
+        "b128" => Some(TokenType::B128),
+        "s8" => Some(TokenType::S8),
+        "s16" => Some(TokenType::S16),
+        "s32" => Some(TokenType::S32),
+        "s64" => Some(TokenType::S64),
+        "s128" => Some(TokenType::S128),
+        _ => None,
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (item, start, end) = match self.lookahead.pop_front() {
+            Some(buffered) => buffered,
+            None => self.produce()?,
+        };
+        self.last_span = (start, end);
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lexer;
+    use crate::compiler::{Token, TokenType};
+
+    fn kind(token: Option<&Result<crate::compiler::Token, super::LexError>>) -> Option<TokenType> {
+        token.map(|token| token.as_ref().unwrap().kind())
+    }
+
+    #[test]
+    fn peeking_does_not_consume() {
+        let mut lexer = Lexer::new("a = b;");
+        assert_eq!(kind(lexer.peek_nth(2)), Some(TokenType::Identifier));
+        assert_eq!(kind(lexer.peek()), Some(TokenType::Identifier));
+        assert_eq!(kind(lexer.peek_nth(1)), Some(TokenType::Assing));
+        assert_eq!(kind(lexer.next().as_ref()), Some(TokenType::Identifier));
+        assert_eq!(lexer.lexeme(), "a");
+        assert_eq!(kind(lexer.next().as_ref()), Some(TokenType::Assing));
+        assert_eq!(lexer.lexeme(), "=");
+        assert_eq!(kind(lexer.peek_nth(2)), Some(TokenType::Eof));
+        assert_eq!(kind(lexer.peek_nth(3)), None);
+        let rest: Vec<TokenType> = lexer.map(|token| token.unwrap().kind()).collect();
+        assert_eq!(rest, [TokenType::Identifier, TokenType::Semicolon, TokenType::Eof]);
+    }
+
+    #[test]
+    fn peeked_trivia_keeps_the_source_lossless() {
+        let source = "b8 x; // x\n";
+        let mut lexer = Lexer::with_trivia(source);
+        assert_eq!(kind(lexer.peek_nth(5)), Some(TokenType::Comment));
+        let mut echoed = String::new();
+        while let Some(token) = lexer.next() {
+            token.unwrap();
+            echoed.push_str(lexer.lexeme());
         }
-        Token::Number(word.parse().unwrap(), self.current_line, self.current_column)
+        assert_eq!(echoed, source);
+    }
+
+    #[test]
+    fn vertical_tab_and_form_feed_are_whitespace() {
+        let kinds: Vec<TokenType> = Lexer::new("a\x0b=\x0c\r\n1;")
+            .map(|token| token.unwrap().kind())
+            .collect();
+        assert_eq!(kinds, [TokenType::Identifier, TokenType::Assing, TokenType::Number, TokenType::Semicolon, TokenType::Eof]);
+    }
+
+    fn strings(source: &str) -> Vec<String> {
+        Lexer::new(source)
+            .map(|token| token.unwrap())
+            .filter(|token| token.kind() == TokenType::StringLiteral)
+            .map(|token| token.text_value())
+            .collect()
+    }
+
+    #[test]
+    fn escapes_in_strings() {
+        assert_eq!(strings(r#""a\n\t\r\0\\\"\x41\u{e9}\u{1F600}""#), ["a\n\t\r\0\\\"A\u{e9}\u{1F600}"]);
+        let error = |source: &str| Lexer::new(source).find_map(|token| token.err()).unwrap().message;
+        assert_eq!(error(r#""\q""#), "Unknown escape sequence \\q");
+        assert_eq!(error(r#""\x4""#), "Expected two hex digits after \\x");
+        assert_eq!(error(r#""\x80""#), "Escape \\x80 is out of the ASCII range");
+    }
+
+    #[test]
+    fn strings_span_lines_unless_continued() {
+        let mut lexer = Lexer::new("\"one\ntwo\" x");
+        assert_eq!(lexer.next().unwrap().unwrap().text_value(), "one\ntwo");
+        assert_eq!(lexer.next().unwrap().unwrap().position().0, 2);
+        assert_eq!(strings("\"one \\\n      two\""), ["one two"]);
+    }
+
+    #[test]
+    fn unterminated_string_points_at_its_start() {
+        let error = Lexer::new("b8 x;\nprint(\"abc\\\"\n")
+            .find_map(|token| token.err())
+            .unwrap();
+        assert_eq!(error.to_string(), "Unterminated string at line 2, column 7 (byte 7)");
+    }
+
+    #[test]
+    fn zero_with_a_width_suffix_is_not_binary() {
+        let numbers: Vec<_> = Lexer::new("0b8 0b16 0b1 0b101_b4")
+            .map(|token| token.unwrap())
+            .filter_map(|token| match token {
+                Token::Number(value, suffix, _, _) => Some((value, suffix)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(numbers, [
+            (0, Some(TokenType::B8)), (0, Some(TokenType::B16)), (1, None), (5, Some(TokenType::B4)),
+        ]);
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let kinds: Vec<TokenType> = Lexer::new("a /* x /* y */ still comment */ b")
+            .map(|token| token.unwrap().kind())
+            .collect();
+        assert_eq!(kinds, [TokenType::Identifier, TokenType::Identifier, TokenType::Eof]);
+    }
+
+    #[test]
+    fn unterminated_block_comment_points_at_its_start() {
+        let error = Lexer::new("b8 x;\n  /* outer /* inner */\n")
+            .find_map(|token| token.err())
+            .unwrap();
+        assert_eq!(error.to_string(), "Unterminated block comment at line 2, column 3 (byte 3)");
+    }
+
+    #[test]
+    fn doc_comments_keep_their_text() {
+        let docs: Vec<String> = Lexer::new("///  Adds one.  \n///\nfunction f() {}")
+            .map(|token| token.unwrap())
+            .filter(|token| token.kind() == TokenType::DocComment)
+            .map(|token| token.text_value())
+            .collect();
+        assert_eq!(docs, [" Adds one.", ""]);
     }
 }
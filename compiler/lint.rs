@@ -0,0 +1,126 @@
+use crate::compiler::{Lexer, Token, TokenType};
+use crate::compiler::prelude::PRELUDE;
+use crate::compiler::types::Type;
+use std::collections::HashSet;
+
+// Words joined by `-` with no whitespace in between
+struct Chain<'a> {
+    words: Vec<&'a str>,
+    start: (usize, usize),
+    // where the old grammar would have declared it as one name
+    declaration: bool,
+}
+
+// `-` used to be allowed inside identifiers, so `total-sum` was one name. It
+// now lexes as `total - sum`. Looks for identifiers directly followed by `-`
+// and another word with no whitespace in between, which is how such names
+// were written, and suggests the `_` spelling. Plain arithmetic like
+// `arr[i-1]` is left alone: a chain is only flagged where the old grammar
+// declared it, wherever a name declared that way is used, or when none of
+// its words is declared on its own.
+pub fn hyphenated_identifiers(source: &str) -> Vec<String> {
+    let mut lexer = Lexer::with_trivia(source);
+    let mut chains = Vec::new();
+    let mut declared: HashSet<&str> = PRELUDE.iter().map(|intrinsic| intrinsic.name).collect();
+    let mut words: Vec<&str> = Vec::new();
+    let mut start = (0, 0);
+    let mut after_minus = false;
+    // whether an identifier here names what is being declared, as after
+    // `b8`, `b8[4]`, `function`, `struct` or a struct's name
+    let mut declares = false;
+    let mut after_type = false;
+    let mut declaring = false;
+    while let Some(Ok(token)) = lexer.next() {
+        let lexeme = lexer.lexeme();
+        let kind = token.kind();
+        let names_declaration = declares;
+        match kind {
+            TokenType::Whitespace | TokenType::Comment | TokenType::DocComment => {}
+            TokenType::LeftBracket | TokenType::Number | TokenType::RightBracket if after_type => {}
+            TokenType::Identifier | TokenType::Function | TokenType::Struct => {
+                declares = true;
+                after_type = false;
+            }
+            _ => {
+                after_type = Type::from_kind(kind).is_some();
+                declares = after_type;
+            }
+        }
+        match kind {
+            TokenType::Identifier if words.is_empty() || after_minus => {
+                if words.is_empty() {
+                    let (line, column) = token.position();
+                    start = (line, column.chars + 1 - lexeme.chars().count());
+                    declaring = names_declaration;
+                }
+                words.push(lexeme);
+                after_minus = false;
+                continue;
+            }
+            TokenType::Number if after_minus && lexeme.bytes().all(|byte| byte.is_ascii_digit()) => {
+                words.push(lexeme);
+                after_minus = false;
+                continue;
+            }
+            // only when a word follows right after it
+            TokenType::Minus if !words.is_empty() && !after_minus
+                && matches!(lexer.peek(), Some(Ok(Token::Identifier(_, _, _))) | Some(Ok(Token::Number(_, _, _, _)))) => {
+                after_minus = true;
+                continue;
+            }
+            _ => {}
+        }
+        flush(&mut words, start, declaring, &mut chains, &mut declared);
+        after_minus = false;
+        if let Token::Identifier(_, line, column) = token {
+            start = (line, column.chars + 1 - lexeme.chars().count());
+            declaring = names_declaration;
+            words.push(lexeme);
+        }
+    }
+    flush(&mut words, start, declaring, &mut chains, &mut declared);
+    let hyphenated: HashSet<String> = chains.iter()
+        .filter(|chain| chain.declaration)
+        .map(|chain| chain.words.join("-"))
+        .collect();
+    chains.iter()
+        .filter(|chain| chain.declaration || hyphenated.contains(&chain.words.join("-"))
+            || !chain.words.iter().any(|word| declared.contains(word)))
+        .map(|chain| format!(
+            "`{}` at line {}, column {} is a subtraction, identifiers can no longer contain `-`; if it is meant as one name, rename it to `{}`",
+            chain.words.join("-"), chain.start.0, chain.start.1, chain.words.join("_")))
+        .collect()
+}
+
+// A lone word in a declaration is a name the chains may refer to
+fn flush<'a>(words: &mut Vec<&'a str>, start: (usize, usize), declaration: bool,
+             chains: &mut Vec<Chain<'a>>, declared: &mut HashSet<&'a str>) {
+    match words.len() {
+        0 => {}
+        1 if declaration => {
+            declared.insert(words[0]);
+        }
+        1 => {}
+        _ => chains.push(Chain { words: words.clone(), start, declaration }),
+    }
+    words.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hyphenated_identifiers;
+
+    #[test]
+    fn names_written_with_a_hyphen_warn() {
+        assert_eq!(hyphenated_identifiers("function main() {\n    b8 total-sum = 1;\n}"), [
+            "`total-sum` at line 2, column 8 is a subtraction, identifiers can no longer contain `-`; \
+                if it is meant as one name, rename it to `total_sum`",
+        ]);
+    }
+
+    #[test]
+    fn subtractions_of_declared_names_stay_silent() {
+        let source = "function main() {\n    b8[4] arr;\n    b8 i = 1;\n    b8 total = arr[i-1];\n    total = total-i;\n}";
+        assert!(hyphenated_identifiers(source).is_empty());
+    }
+}
@@ -1,27 +1,73 @@
 pub mod lexer;
 pub mod parser;
 pub mod constants;
+pub mod types;
+pub mod interner;
+pub mod ast;
+pub mod unicode;
+pub mod lint;
+pub mod interpreter;
+pub mod data;
+pub mod prelude;
+mod unicode_tables;
+
+use self::interner::Symbol;
+use std::fmt;
+
+// Where a token ends on its line, in characters and in UTF-8 bytes. The two
+// only differ after non-ASCII text, and the byte count is only shown then.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub struct Column {
+    pub chars: usize,
+    pub bytes: usize,
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.chars == self.bytes {
+            write!(f, "{}", self.chars)
+        } else {
+            write!(f, "{} (byte {})", self.chars, self.bytes)
+        }
+    }
+}
+
+impl fmt::Debug for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     // Keyword
-    B1(usize, usize), B2(usize, usize), B4(usize, usize), B8(usize, usize),
-    B16(usize, usize), B32(usize, usize), B64(usize, usize), B128(usize, usize),
-    Function(usize, usize), If(usize, usize), Else(usize, usize), While(usize, usize),
-    Return(usize, usize), Break(usize, usize), Continue(usize, usize), Print(usize, usize),
-    True(usize, usize), False(usize, usize),
+    B1(usize, Column), B2(usize, Column), B4(usize, Column), B8(usize, Column),
+    B16(usize, Column), B32(usize, Column), B64(usize, Column), B128(usize, Column),
+    S8(usize, Column), S16(usize, Column), S32(usize, Column), S64(usize, Column), S128(usize, Column),
+    Function(usize, Column), If(usize, Column), Else(usize, Column), While(usize, Column),
+    Return(usize, Column), Break(usize, Column), Continue(usize, Column), Print(usize, Column),
+    True(usize, Column), False(usize, Column), As(usize, Column), Struct(usize, Column), Const(usize, Column), For(usize, Column), Do(usize, Column), Match(usize, Column), Println(usize, Column),
 
     // Symbols
-    Assing(usize, usize), Equal(usize, usize), NotEqual(usize, usize), Greater(usize, usize),
-    GreaterEqual(usize, usize), Less(usize, usize), LessEqual(usize, usize),
-    Plus(usize, usize), Minus(usize, usize), Star(usize, usize), Slash(usize, usize),
-    LeftParenthesis(usize, usize), RightParenthesis(usize, usize), LeftBraces(usize, usize),
-    RightBraces(usize, usize), Comma(usize, usize), Semicolon(usize, usize),
+    Assing(usize, Column), Equal(usize, Column), NotEqual(usize, Column), Greater(usize, Column),
+    GreaterEqual(usize, Column), Less(usize, Column), LessEqual(usize, Column),
+    Plus(usize, Column), Minus(usize, Column), Star(usize, Column), Slash(usize, Column),
+    LeftParenthesis(usize, Column), RightParenthesis(usize, Column), LeftBraces(usize, Column),
+    RightBraces(usize, Column), LeftBracket(usize, Column), RightBracket(usize, Column),
+    Comma(usize, Column), Semicolon(usize, Column), Dot(usize, Column), Arrow(usize, Column), Colon(usize, Column), FatArrow(usize, Column), DotDotEq(usize, Column),
+    And(usize, Column), Or(usize, Column), Not(usize, Column),
+    Ampersand(usize, Column), Pipe(usize, Column), Caret(usize, Column), Tilde(usize, Column),
+    Percent(usize, Column), ShiftLeft(usize, Column), ShiftRight(usize, Column),
 
     // Literals
-    Identifier(String, usize, usize), Number(i64, usize, usize), StringLiteral(String, usize, usize),
+    // Number carries the optional width suffix of the literal, as in 255b8
+    Identifier(Symbol, usize, Column), Number(u128, Option<TokenType>, usize, Column),
+    StringLiteral(Symbol, usize, Column), DocComment(String, usize, Column),
 
-    EOF(usize, usize),
+    // Trivia, only produced by a lossless lexer
+    Whitespace(String, usize, Column), Comment(String, usize, Column),
+
+    Eof(usize, Column),
 }
 
 impl Token {
@@ -35,6 +81,11 @@ impl Token {
             Token::B32(_, _) => TokenType::B32,
             Token::B64(_, _) => TokenType::B64,
             Token::B128(_, _) => TokenType::B128,
+            Token::S8(_, _) => TokenType::S8,
+            Token::S16(_, _) => TokenType::S16,
+            Token::S32(_, _) => TokenType::S32,
+            Token::S64(_, _) => TokenType::S64,
+            Token::S128(_, _) => TokenType::S128,
             Token::Function(_, _) => TokenType::Function,
             Token::If(_, _) => TokenType::If,
             Token::Else(_, _) => TokenType::Else,
@@ -45,6 +96,13 @@ impl Token {
             Token::Print(_, _) => TokenType::Print,
             Token::True(_, _) => TokenType::True,
             Token::False(_, _) => TokenType::False,
+            Token::As(_, _) => TokenType::As,
+            Token::Struct(_, _) => TokenType::Struct,
+            Token::Const(_, _) => TokenType::Const,
+            Token::For(_, _) => TokenType::For,
+            Token::Do(_, _) => TokenType::Do,
+            Token::Match(_, _) => TokenType::Match,
+            Token::Println(_, _) => TokenType::Println,
             Token::Assing(_, _) => TokenType::Assing,
             Token::Equal(_, _) => TokenType::Equal,
             Token::NotEqual(_, _) => TokenType::NotEqual,
@@ -60,48 +118,70 @@ impl Token {
             Token::RightParenthesis(_, _) => TokenType::RightParenthesis,
             Token::LeftBraces(_, _) => TokenType::LeftBraces,
             Token::RightBraces(_, _) => TokenType::RightBraces,
+            Token::LeftBracket(_, _) => TokenType::LeftBracket,
+            Token::RightBracket(_, _) => TokenType::RightBracket,
             Token::Comma(_, _) => TokenType::Comma,
             Token::Semicolon(_, _) => TokenType::Semicolon,
+            Token::Colon(_, _) => TokenType::Colon,
+            Token::FatArrow(_, _) => TokenType::FatArrow,
+            Token::DotDotEq(_, _) => TokenType::DotDotEq,
+            Token::Dot(_, _) => TokenType::Dot,
+            Token::Arrow(_, _) => TokenType::Arrow,
+            Token::And(_, _) => TokenType::And,
+            Token::Or(_, _) => TokenType::Or,
+            Token::Not(_, _) => TokenType::Not,
+            Token::Ampersand(_, _) => TokenType::Ampersand,
+            Token::Pipe(_, _) => TokenType::Pipe,
+            Token::Caret(_, _) => TokenType::Caret,
+            Token::Tilde(_, _) => TokenType::Tilde,
+            Token::Percent(_, _) => TokenType::Percent,
+            Token::ShiftLeft(_, _) => TokenType::ShiftLeft,
+            Token::ShiftRight(_, _) => TokenType::ShiftRight,
             Token::Identifier(_, _, _) => TokenType::Identifier,
-            Token::Number(_, _, _) => TokenType::Number,
+            Token::Number(_, _, _, _) => TokenType::Number,
             Token::StringLiteral(_, _, _) => TokenType::StringLiteral,
-            Token::EOF(_, _) => TokenType::EOF,
+            Token::DocComment(_, _, _) => TokenType::DocComment,
+            Token::Whitespace(_, _, _) => TokenType::Whitespace,
+            Token::Comment(_, _, _) => TokenType::Comment,
+            Token::Eof(_, _) => TokenType::Eof,
         }
     }
 
-    fn position(&self) -> (usize, usize) {
+    fn position(&self) -> (usize, Column) {
         match self {
             Token::B1(line, col) | Token::B2(line, col) | Token::B4(line, col) | Token::B8(line, col) |
             Token::B16(line, col) | Token::B32(line, col) | Token::B64(line, col) | Token::B128(line, col) |
+            Token::S8(line, col) | Token::S16(line, col) | Token::S32(line, col) | Token::S64(line, col) |
+            Token::S128(line, col) |
             Token::Function(line, col) | Token::If(line, col) | Token::Else(line, col) | Token::While(line, col) |
             Token::Return(line, col) | Token::Break(line, col) | Token::Continue(line, col) | Token::Print(line, col) |
-            Token::True(line, col) | Token::False(line, col) | Token::Assing(line, col) | Token::Equal(line, col) |
+            Token::True(line, col) | Token::False(line, col) | Token::As(line, col) | Token::Struct(line, col) | Token::Const(line, col) | Token::For(line, col) | Token::Do(line, col) | Token::Match(line, col) | Token::Println(line, col) | Token::Assing(line, col) | Token::Equal(line, col) |
             Token::NotEqual(line, col) | Token::Greater(line, col) | Token::GreaterEqual(line, col) | Token::Less(line, col) |
             Token::LessEqual(line, col) | Token::Plus(line, col) | Token::Minus(line, col) | Token::Star(line, col) |
             Token::Slash(line, col) | Token::LeftParenthesis(line, col) | Token::RightParenthesis(line, col) |
-            Token::LeftBraces(line, col) | Token::RightBraces(line, col) | Token::Comma(line, col) | Token::Semicolon(line, col) |
-            Token::Identifier(_, line, col) | Token::Number(_, line, col) | Token::StringLiteral(_, line, col) | 
-            Token::EOF(line, col) => (*line, *col),
+            Token::LeftBraces(line, col) | Token::RightBraces(line, col) | Token::LeftBracket(line, col) |
+            Token::RightBracket(line, col) | Token::Comma(line, col) | Token::Semicolon(line, col) | Token::Dot(line, col) | Token::Arrow(line, col) | Token::Colon(line, col) | Token::FatArrow(line, col) | Token::DotDotEq(line, col) |
+            Token::And(line, col) | Token::Or(line, col) | Token::Not(line, col) |
+            Token::Ampersand(line, col) | Token::Pipe(line, col) | Token::Caret(line, col) |
+            Token::Tilde(line, col) | Token::Percent(line, col) | Token::ShiftLeft(line, col) |
+            Token::ShiftRight(line, col) |
+            Token::Identifier(_, line, col) | Token::Number(_, _, line, col) | Token::StringLiteral(_, line, col) |
+            Token::DocComment(_, line, col) | Token::Whitespace(_, line, col) | Token::Comment(_, line, col) | 
+            Token::Eof(line, col) => (*line, *col),
         }
     }
 
     fn text_value(&self) -> String {
         match self {
-            Token::Identifier(value, _, _) => value.clone(),
-            Token::StringLiteral(value, _, _) => value.clone(),
-            _ => panic!("LMAO"),
-        }
-    }
-
-    fn number_value(&self) -> i64 {
-        match self {
-            Token::Number(value, _, _) => value.clone(),
+            Token::Identifier(value, _, _) | Token::StringLiteral(value, _, _) => value.to_string(),
+            Token::DocComment(value, _, _) => value.clone(),
+            Token::Whitespace(value, _, _) | Token::Comment(value, _, _) => value.clone(),
             _ => panic!("LMAO"),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     B1,
     B2,
@@ -111,6 +191,11 @@ pub enum TokenType {
     B32,
     B64,
     B128,
+    S8,
+    S16,
+    S32,
+    S64,
+    S128,
     Function,
     If,
     Else,
@@ -121,6 +206,13 @@ pub enum TokenType {
     Print,
     True,
     False,
+    As,
+    Struct,
+    Const,
+    For,
+    Do,
+    Match,
+    Println,
     Assing,
     Equal,
     NotEqual,
@@ -136,12 +228,32 @@ pub enum TokenType {
     RightParenthesis,
     LeftBraces,
     RightBraces,
+    LeftBracket,
+    RightBracket,
     Comma,
     Semicolon,
+    Colon,
+    FatArrow,
+    DotDotEq,
+    Dot,
+    Arrow,
+    And,
+    Or,
+    Not,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Percent,
+    ShiftLeft,
+    ShiftRight,
     Identifier,
     Number,
     StringLiteral,
-    EOF,
+    DocComment,
+    Whitespace,
+    Comment,
+    Eof,
 }
 
 pub use self::lexer::Lexer;
@@ -1,305 +1,1677 @@
-use crate::compiler::{Token, TokenType};
+use crate::compiler::{Column, Token, TokenType};
+use crate::compiler::ast::{Ast, Binding, Dispatch, FormatPiece, FormatSpec, NodeId, NodeKind};
+use crate::compiler::data::DataSection;
+use crate::compiler::interner::Symbol;
+use crate::compiler::prelude::{self, Builtin, Param, PRELUDE};
+use crate::compiler::types::{Expr, FieldType, StructType, Type};
 use std::iter::Peekable;
 use std::slice::Iter;
 
 pub struct Parser<'a> {
     tokens: Peekable<Iter<'a, Token>>,
-    on_while: bool,
-    on_function: bool,
-    symbol_table: Vec<Symbol>, 
+    // Functions and loops enclosing the statement being parsed, innermost
+    // last
+    contexts: Vec<Context>,
+    // Symbols from here on belong to the function being parsed. Only those
+    // are checked for being read before they are assigned, globals may be
+    // assigned by the top-level code before any function runs.
+    locals_start: usize,
+    // Slots taken by the globals, and by the function being parsed, which
+    // is None at top level
+    global_slots: usize,
+    local_slots: Option<usize>,
+    symbol_table: Vec<SymbolEntry>, 
+    // First symbol of the innermost block, function or `for` loop
+    scope_start: usize,
+    // What is assigned at every `break` seen so far out of each loop being
+    // parsed, innermost last
+    loop_breaks: Vec<Option<Vec<bool>>>,
+    // Struct types are kept apart from variables and functions, but share
+    // the global namespace with them
+    type_table: Vec<TypeEntry>,
     last_expect_line: usize,
-    last_expect_column: usize,
+    last_expect_column: Column,
+    pending_doc: Option<String>,
+    ast: Ast,
+    data: DataSection,
 }
 
-struct Symbol {
-    id: String,
-    tp: Token,
+struct SymbolEntry {
+    id: Symbol,
+    kind: SymbolKind,
+    doc: Option<String>,
+    // Whether the variable has been assigned on every path reaching the
+    // statement being parsed
+    assigned: bool,
+    // Locals go out of scope at the end of the block they are declared in
+    in_scope: bool,
+    line: usize,
+    // Slot of a variable or constant, None for functions
+    binding: Option<Binding>,
+}
+
+struct TypeEntry {
+    tp: StructType,
+    doc: Option<String>,
+    line: usize,
+    field_docs: Vec<(Symbol, String)>,
+}
+
+#[derive(Clone, PartialEq)]
+enum SymbolKind {
+    Variable(Type),
+    // Type and value
+    Constant(Type, i128),
+    // Element type and length. Array parameters declared as `b8[]` take
+    // arrays of any length.
+    Array(Type, Option<usize>),
+    // Variable of the named struct type
+    Struct(Symbol),
+    // Kinds of the parameters, in order, and the return type, if any
+    Function(Vec<SymbolKind>, Option<Type>),
+}
+
+impl std::fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SymbolKind::Variable(tp) => write!(f, "{}", tp),
+            SymbolKind::Constant(tp, _) => write!(f, "const {}", tp),
+            SymbolKind::Array(tp, Some(len)) => write!(f, "{}[{}]", tp, len),
+            SymbolKind::Array(tp, None) => write!(f, "{}[]", tp),
+            SymbolKind::Struct(name) => write!(f, "{}", name),
+            SymbolKind::Function(_, Some(ret)) => write!(f, "function -> {}", ret),
+            SymbolKind::Function(_, None) => write!(f, "function"),
+        }
+    }
+}
+
+// Statements that `break`, `continue` and `return` refer to
+enum Context {
+    // Name and return type
+    Function(Symbol, Option<Type>),
+    // Label, if the loop has one
+    Loop(Option<Symbol>),
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Parser {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        let mut parser = Parser {
             tokens: tokens.iter().peekable(),
-            on_while: false,
-            on_function: false,
-            symbol_table: Vec::<Symbol>::new(),
+            contexts: Vec::new(),
+            locals_start: 0,
+            global_slots: 0,
+            local_slots: None,
+            symbol_table: Vec::<SymbolEntry>::new(),
+            scope_start: 0,
+            loop_breaks: Vec::new(),
+            type_table: Vec::new(),
             last_expect_line: 1,
-            last_expect_column: 0,
+            last_expect_column: Column::default(),
+            pending_doc: None,
+            ast: Ast::new(),
+            data: DataSection::new(),
+        };
+        parser.declare_prelude();
+        parser
+    }
+
+    fn declare_prelude(&mut self) {
+        for intrinsic in PRELUDE.iter() {
+            let params = intrinsic.params.iter().map(|&param| match param {
+                Param::Int(tp) => SymbolKind::Variable(tp),
+                Param::Buffer(tp) => SymbolKind::Array(tp, None),
+            }).collect();
+            let kind = SymbolKind::Function(params, intrinsic.ret);
+            self.symbol_table.push(SymbolEntry{id: Symbol::intern(intrinsic.name), kind, doc: None, assigned: true, in_scope: true, line: 0, binding: None});
         }
     }
     
-    pub fn parse(&mut self) {
-        while self.tokens.peek().unwrap().kind() != TokenType::EOF {
-            self.parse_statement();
+    // Returns the Program node, the root of the syntax tree. A program is a
+    // list of declarations, one of them the `main` function where it starts.
+    pub fn parse(&mut self) -> NodeId {
+        self.collect_functions();
+        let mut body = Vec::new();
+        while !self.closes(TokenType::Eof) {
+            body.push(self.parse_declaration());
+        }
+        self.check_main();
+        self.ast.add(NodeKind::Program(body), 1)
+    }
+
+    // First pass over the tokens: every function's signature goes in the
+    // symbol table before any body is parsed, so functions can call each
+    // other whatever order they are declared in. Struct parameter types are
+    // checked once the function itself is parsed.
+    fn collect_functions(&mut self) {
+        let (start, expect_line, expect_column) = (self.tokens.clone(), self.last_expect_line, self.last_expect_column);
+        while let Some(token) = self.tokens.next() {
+            let (name, line) = match (token, self.tokens.peek()) {
+                (&Token::Function(_,_), Some(&&Token::Identifier(name,line,_))) => (name, line),
+                _ => continue,
+            };
+            self.tokens.next(); // consume name
+            if prelude::intrinsic(name.as_str()).is_some() {
+                panic!("Function '{}' is built in and cannot be declared again, line {}", name, line);
+            }
+            if self.lookup_symbol(name).is_some() {
+                panic!("Function '{}' is declared more than once, line {}", name, line);
+            }
+            self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
+            let mut params = Vec::new();
+            while let Some((_, _, kind)) = self.parse_param() {
+                params.push(kind);
+            }
+            self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+            let ret = self.parse_return_type();
+            self.symbol_table.push(SymbolEntry{id: name, kind: SymbolKind::Function(params, ret), doc: None, assigned: true, in_scope: true, line, binding: None});
+        }
+        self.tokens = start;
+        self.last_expect_line = expect_line;
+        self.last_expect_column = expect_column;
+    }
+
+    // Globals, constants, structs and functions. Any other statement has to
+    // be inside a function.
+    fn parse_declaration(&mut self) -> NodeId {
+        let token = *self.tokens.peek().unwrap();
+        match token.kind() {
+            TokenType::DocComment => {
+                self.parse_doc_comment();
+                self.parse_declaration()
+            }
+            TokenType::Function | TokenType::Struct | TokenType::Const => self.parse_statement(),
+            _ if Type::from_token(token).is_some() || self.struct_var_decl_ahead() => self.parse_statement(),
+            _ => {
+                let (line, column) = token.position();
+                panic!("Only declarations are allowed at top level, found {:?} at line {}, column {}; move it into a function",
+                    token.kind(), line, column);
+            }
         }
     }
 
-    fn parse_statement(&mut self) {
-        println!("Token to be read: {:?}", self.tokens.peek());
+    // `main` takes no parameters. If it returns a value, that is the exit
+    // status of the program.
+    fn check_main(&self) {
+        let main = Symbol::intern("main");
+        match self.lookup_symbol(main).map(|symbol| &symbol.kind) {
+            Some(SymbolKind::Function(params, _)) if params.is_empty() => {}
+            Some(SymbolKind::Function(params, _)) => {
+                panic!("'main' must not take parameters, found {}", params.len());
+            }
+            _ => panic!("Program has no 'main' function, declare `function main()` where the program starts"),
+        }
+    }
+
+    // Name and return type of the function being parsed
+    fn current_function(&self) -> Option<(Symbol, Option<Type>)> {
+        self.contexts.iter().rev().filter_map(|context| match *context {
+            Context::Function(name, ret) => Some((name, ret)),
+            Context::Loop(_) => None,
+        }).next()
+    }
+
+    // Loops around the statement being parsed, within its function
+    fn loop_depth(&self) -> usize {
+        self.enclosing_loops().count()
+    }
+
+    fn enclosing_loops<'b>(&'b self) -> impl Iterator<Item = Option<Symbol>> + 'b {
+        self.contexts.iter().rev().map_while(|context| match *context {
+            Context::Loop(label) => Some(label),
+            Context::Function(_, _) => None,
+        })
+    }
+
+    pub fn data_section(&self) -> &DataSection {
+        &self.data
+    }
+
+    pub fn ast(&self) -> &Ast {
+        &self.ast
+    }
+
+    fn parse_statement(&mut self) -> NodeId {
+        if self.struct_var_decl_ahead() {
+            return self.parse_struct_var_decl();
+        }
+        if self.label_ahead() {
+            return self.parse_labeled_loop();
+        }
         match self.tokens.peek() {
             Some(Token::B1(_,_)) | Some(Token::B2(_,_)) | Some(Token::B4(_,_))
             | Some(Token::B8(_,_)) | Some(Token::B16(_,_))
             | Some(Token::B32(_,_)) | Some(Token::B64(_,_))
-            | Some(Token::B128(_,_)) => {
-                self.parse_var_decl();
+            | Some(Token::B128(_,_)) | Some(Token::S8(_,_))
+            | Some(Token::S16(_,_)) | Some(Token::S32(_,_))
+            | Some(Token::S64(_,_)) | Some(Token::S128(_,_))
+            | Some(Token::Const(_,_)) => {
+                self.parse_var_decl()
+            }
+            Some(Token::DocComment(_,_,_)) => {
+                self.parse_doc_comment();
+                self.parse_statement()
             }
             Some(Token::Function(_,_)) => self.parse_func_decl(),
+            Some(Token::Struct(_,_)) => self.parse_struct_decl(),
             Some(Token::If(_,_)) => self.parse_if_stmt(),
-            Some(Token::While(_,_)) => self.parse_while_stmt(),
-            Some(Token::Print(_,_)) => self.parse_print_stmt(),
-            Some(Token::Break(_,_)) => {
-                if self.on_while {
-                    self.parse_break_stmt();
-                } else {
-                    panic!("Unexpected break token on statement: {:?}", self.tokens.peek());
-                }
+            Some(Token::While(_,_)) => self.parse_while_stmt(None),
+            Some(Token::For(_,_)) => self.parse_for_stmt(None),
+            Some(Token::Match(_,_)) => self.parse_match_stmt(),
+            Some(Token::Do(_,_)) => self.parse_do_while_stmt(None),
+            Some(Token::Print(_,_)) | Some(Token::Println(_,_)) => self.parse_print_stmt(),
+            Some(Token::Break(_,_)) => self.parse_break_stmt(),
+            Some(Token::Continue(_,_)) => self.parse_continue_stmt(),
+            Some(Token::Return(_,_)) => self.parse_return_stmt(),
+            Some(Token::Identifier(_,_,_)) => self.parse_assign_or_func_call(),
+            _ => panic!("Unexpected token on statement: {:?}", self.tokens.peek()),
+        }
+    }
+
+    // Consecutive `///` lines document the function, struct or variable
+    // declared right after them. Before anything else they are plain
+    // comments.
+    fn parse_doc_comment(&mut self) {
+        let doc = self.take_doc_comment();
+        let token = *self.tokens.peek().unwrap();
+        if matches!(token.kind(), TokenType::Function | TokenType::Struct | TokenType::Const)
+            || Type::from_token(token).is_some() || self.struct_var_decl_ahead() {
+            self.pending_doc = doc;
+        }
+    }
+
+    fn take_doc_comment(&mut self) -> Option<String> {
+        let mut lines = Vec::new();
+        while let Some(Token::DocComment(text,_,_)) = self.tokens.peek() {
+            lines.push(text.clone());
+            self.tokens.next();
+        }
+        if lines.is_empty() { None } else { Some(lines.join("\n")) }
+    }
+
+    // Whether `end` comes next, closing a block or the program. Doc comments
+    // right before it document nothing and are skipped.
+    fn closes(&mut self, end: TokenType) -> bool {
+        let mut lookahead = self.tokens.clone();
+        while let Some(Token::DocComment(_,_,_)) = lookahead.peek() {
+            lookahead.next();
+        }
+        let closes = lookahead.peek().unwrap().kind() == end;
+        if closes {
+            self.tokens = lookahead;
+        }
+        closes
+    }
+
+    // Reference documentation for every documented declaration, in the order
+    // they appear in the source.
+    pub fn reference_docs(&self) -> String {
+        let mut docs = String::new();
+        for entry in self.type_table.iter() {
+            if entry.doc.is_none() && entry.field_docs.is_empty() {
+                continue;
             }
-            Some(Token::Continue(_,_)) => {
-                if self.on_while {
-                    self.parse_continue_stmt();
-                } else {
-                    panic!("Unexpected continue token on statement: {:?}", self.tokens.peek());
-                }
+            docs.push_str(&format!("## {} (struct, {} bytes)\n\n", entry.tp.name, entry.tp.size));
+            if let Some(ref doc) = entry.doc {
+                docs.push_str(&format!("{}\n\n", doc));
             }
-            Some(Token::Return(_,_)) => {
-                if self.on_function {
-                    self.parse_return_stmt();
-                } else {
-                    panic!("Unexpected return token on statement: {:?}", self.tokens.peek());
-                }
+            for (field, doc) in entry.field_docs.iter() {
+                docs.push_str(&format!("- `{}`: {}\n", field, doc.replace('\n', " ")));
+            }
+            if !entry.field_docs.is_empty() {
+                docs.push('\n');
+            }
+        }
+        for symbol in self.symbol_table.iter() {
+            if let Some(ref doc) = symbol.doc {
+                docs.push_str(&format!("## {} ({})\n\n{}\n\n", symbol.id, symbol.kind, doc));
             }
-            Some(Token::Identifier(_,_,_)) => self.parse_assign_or_func_call(),
-            _ => panic!("Unexpected token on statement: {:?}", self.tokens.peek()),
         }
+        docs
     }
 
-    fn parse_var_decl(&mut self) {
-        //println!("we are here VAR");
-        //println!("token: {:?}", self.tokens.peek());
+    // `b8 a = 1, b;` declares the names in order, so an initializer can use
+    // the names before it. Arrays share the dimension written after the type
+    // and start zeroed, they take no initializer. A doc comment documents
+    // the first name.
+    fn parse_var_decl(&mut self) -> NodeId {
+        let constant = if let Some(Token::Const(_,_)) = self.tokens.peek() {
+            self.tokens.next(); // consume 'const'
+            true
+        } else {
+            false
+        };
         let var_type = self.tokens.next().unwrap();
-        let var_name = self.tokens.next();
-        println!("new variable to be declared: {:?}", var_name);
-        if let Some(Token::Identifier(name,_,_)) = var_name {
-            self.symbol_table.push(Symbol{id: name.clone(), tp: var_type.clone()});
-            if let Some(Token::Semicolon(_,_)) = self.tokens.peek() {
-                self.tokens.next();
-                return
+        let tp = match Type::from_token(var_type) {
+            Some(tp) => tp,
+            None => panic!("Expected a type after 'const', found {:?}", var_type),
+        };
+        let dimension = self.parse_array_dimension();
+        if constant && dimension.is_some() {
+            panic!("Constants must be integers, found an array at line {}", var_type.position().0);
+        }
+        let mut declarations = Vec::new();
+        loop {
+            let var_name = self.tokens.next();
+            let (name, line) = match var_name {
+                Some(&Token::Identifier(name,line,_)) => (name, line),
+                _ => {
+                    self.report_error("var_decl");
+                    panic!("Unexpected token in variable declaration: {:?}", var_name);
+                }
+            };
+            let doc = self.pending_doc.take();
+            let node = match dimension {
+                Some(None) => panic!("Array '{}' needs a length at line {}", name, line),
+                Some(Some(len)) => {
+                    if let Some(Token::Assing(_,_)) = self.tokens.peek() {
+                        panic!("Array '{}' cannot have an initializer, line {}", name, line);
+                    }
+                    if self.current_function().is_none() {
+                        self.data.add(name, tp.size() * len, tp.size());
+                    }
+                    self.declare(name, SymbolKind::Array(tp, Some(len)), doc, true, line);
+                    NodeKind::ArrayDecl{name, tp, len}
+                }
+                None if constant => {
+                    let value = self.parse_const_value(name, tp, line);
+                    self.declare(name, SymbolKind::Constant(tp, value), doc, true, line);
+                    NodeKind::ConstDecl{name, tp, value}
+                }
+                None => {
+                    self.declare(name, SymbolKind::Variable(tp), doc, false, line);
+                    let init = self.parse_initializer(tp);
+                    if self.current_function().is_none() {
+                        self.global(name, tp, init.as_ref(), line);
+                    }
+                    self.symbol_table.last_mut().unwrap().assigned = init.is_some() || self.current_function().is_none();
+                    NodeKind::VarDecl{name, tp, init: init.map(|init| init.node)}
+                }
+            };
+            let node = self.ast.add(node, line);
+            self.resolve(node, name);
+            declarations.push(node);
+            if let Some(Token::Comma(_,_)) = self.tokens.peek() {
+                self.tokens.next(); // consume ','
             } else {
-                self.report_error("var_decl");
-                panic!("Unexpected token in variable declaration: {:?}", self.tokens.peek());
+                self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+                break;
             }
+        }
+        if declarations.len() == 1 {
+            declarations[0]
         } else {
-            panic!("Unexpected token in variable declaration: {:?}", self.tokens.peek());
+            let line = self.ast.get(declarations[0]).line;
+            self.ast.add(NodeKind::Declarations(declarations), line)
+        }
+    }
+
+    // Globals live in the data section. They start out zeroed unless they
+    // are initialized, and since that happens before any code runs, the
+    // initial value must be a constant.
+    fn global(&mut self, name: Symbol, tp: Type, init: Option<&Expr>, line: usize) {
+        let offset = self.data.add(name, tp.size(), tp.size());
+        match init.map(|init| init.constant) {
+            Some(Some(value)) => self.data.initialize(offset, tp.size(), tp.truncate(value)),
+            Some(None) => panic!("Global '{}' must be initialized with a constant expression, line {}", name, line),
+            None => {}
         }
     }
+
+    // `= value` after a variable name, checked like an assignment
+    fn parse_initializer(&mut self, tp: Type) -> Option<Expr> {
+        if let Some(Token::Assing(_,_)) = self.tokens.peek() {
+            let op = self.tokens.next().unwrap(); // consume '='
+            let value = self.parse_expression();
+            value.check_assignable(tp, op);
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    // Constants are folded into the expressions that use them, so their
+    // value has to be known when the program is compiled
+    fn parse_const_value(&mut self, name: Symbol, tp: Type, line: usize) -> i128 {
+        let op = match self.tokens.next() {
+            Some(op @ Token::Assing(_,_)) => op,
+            token => panic!("Constant '{}' needs a value at line {}, found {:?}", name, line, token),
+        };
+        let value = self.parse_expression();
+        value.check_assignable(tp, op);
+        match value.constant {
+            Some(constant) => tp.truncate(constant),
+            None => panic!("The value of constant '{}' must be known at compile time, line {}", name, line),
+        }
+    }
+
+    // Two identifiers in a row can only start a declaration whose type is a
+    // struct: `Point origin;`
+    fn struct_var_decl_ahead(&self) -> bool {
+        let mut lookahead = self.tokens.clone();
+        matches!((lookahead.next(), lookahead.next()), (Some(Token::Identifier(_,_,_)), Some(Token::Identifier(_,_,_))))
+    }
+
+    fn lookup_type(&self, name: Symbol) -> Option<usize> {
+        self.type_table.iter().position(|entry| entry.tp.name == name)
+    }
+
+    // Consumes the name of a declared struct type
+    fn parse_struct_type_name(&mut self) -> (Symbol, usize) {
+        match self.tokens.next() {
+            Some(&Token::Identifier(name, line, column)) => match self.lookup_type(name) {
+                Some(index) => (name, index),
+                None => panic!("Unknown type '{}' at line {}, column {}", name, line, column),
+            },
+            token => panic!("Expected a type name, found {:?}", token),
+        }
+    }
+
+    fn parse_struct_var_decl(&mut self) -> NodeId {
+        let (struct_name, index) = self.parse_struct_type_name();
+        let slots = self.type_table[index].tp.slots;
+        match self.tokens.next() {
+            Some(&Token::Identifier(name,line,_)) => {
+                if self.current_function().is_none() {
+                    let (size, align) = (self.type_table[index].tp.size, self.type_table[index].tp.align);
+                    self.data.add(name, size, align);
+                }
+                let doc = self.pending_doc.take();
+                self.declare(name, SymbolKind::Struct(struct_name), doc, true, line);
+                self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+                let node = self.ast.add(NodeKind::StructVarDecl{name, struct_name, slots}, line);
+                self.resolve(node, name);
+                node
+            }
+            token => panic!("Unexpected token in variable declaration: {:?}", token),
+        }
+    }
+
+    // Structs are declared at top level. A field may have the type of a
+    // struct declared before it, so a struct can never contain itself.
+    fn parse_struct_decl(&mut self) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume 'struct'
+        if self.current_function().is_some() {
+            panic!("Structs must be declared at top level, line {}", line);
+        }
+        let name = match self.tokens.next() {
+            Some(&Token::Identifier(name,_,_)) => name,
+            token => panic!("Expected struct name after 'struct', found {:?}", token),
+        };
+        if self.lookup_type(name).is_some() {
+            panic!("Struct '{}' is already declared, line {}", name, line);
+        }
+        self.check_global_name(name, line);
+        self.expect(Token::LeftBraces(self.last_expect_line,self.last_expect_column));
+        let mut fields = Vec::new();
+        let mut field_docs = Vec::new();
+        while !self.closes(TokenType::RightBraces) {
+            let doc = self.take_doc_comment();
+            let tp = match self.tokens.peek() {
+                Some(&token) if Type::from_token(token).is_some() => {
+                    self.tokens.next();
+                    FieldType::Integer(Type::from_token(token).unwrap())
+                }
+                _ => FieldType::Struct(self.parse_struct_type_name().1),
+            };
+            match self.tokens.next() {
+                Some(&Token::Identifier(field,line,column)) => {
+                    if fields.iter().any(|&(other, _)| other == field) {
+                        panic!("Duplicate field '{}' in struct '{}' at line {}, column {}", field, name, line, column);
+                    }
+                    fields.push((field, tp));
+                    if let Some(doc) = doc {
+                        field_docs.push((field, doc));
+                    }
+                }
+                token => panic!("Expected field name, found {:?}", token),
+            }
+            self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        }
+        self.expect(Token::RightBraces(self.last_expect_line,self.last_expect_column));
+        if fields.is_empty() {
+            panic!("Struct '{}' has no fields, line {}", name, line);
+        }
+
+        let layout = StructType::layout(name, &fields, &self.type_table.iter().map(|entry| &entry.tp).collect::<Vec<_>>());
+        let mut nodes = Vec::new();
+        for field in layout.fields.iter() {
+            let kind = match field.tp {
+                FieldType::Integer(tp) => NodeKind::FieldDecl{name: field.name, tp, offset: field.offset},
+                FieldType::Struct(index) => NodeKind::StructFieldDecl{
+                    name: field.name,
+                    struct_name: self.type_table[index].tp.name,
+                    offset: field.offset,
+                },
+            };
+            nodes.push(self.ast.add(kind, line));
+        }
+        let (size, align) = (layout.size, layout.align);
+        self.type_table.push(TypeEntry{tp: layout, doc: self.pending_doc.take(), line, field_docs});
+        self.ast.add(NodeKind::StructDecl{name, fields: nodes, size, align}, line)
+    }
+
+    // `.field` accesses after a struct variable, up to an integer field.
+    // Returns the path of field names, the field's type and its slot.
+    fn parse_field_path(&mut self, name: Symbol) -> (Vec<Symbol>, Type, usize) {
+        let mut struct_index = match self.lookup_symbol(name).map(|symbol| &symbol.kind) {
+            Some(&SymbolKind::Struct(struct_name)) => self.lookup_type(struct_name).unwrap(),
+            _ => panic!("'{}' is not a struct, line {}", name, self.tokens.peek().unwrap().position().0),
+        };
+        let mut path = Vec::new();
+        let mut slot = 0;
+        loop {
+            self.expect(Token::Dot(self.last_expect_line,self.last_expect_column));
+            let (field_name, line, column) = match self.tokens.next() {
+                Some(&Token::Identifier(field_name, line, column)) => (field_name, line, column),
+                token => panic!("Expected field name after '.', found {:?}", token),
+            };
+            let tp = &self.type_table[struct_index].tp;
+            let field = match tp.field(field_name) {
+                Some(field) => field,
+                None => panic!("Struct '{}' has no field '{}', line {}, column {}", tp.name, field_name, line, column),
+            };
+            path.push(field_name);
+            slot += field.slot;
+            let more = self.tokens.peek().unwrap().kind() == TokenType::Dot;
+            match field.tp {
+                FieldType::Integer(tp) if !more => return (path, tp, slot),
+                FieldType::Integer(tp) => panic!("Field '{}' is a {}, not a struct, line {}, column {}",
+                    field_name, tp, line, column),
+                FieldType::Struct(index) if more => struct_index = index,
+                FieldType::Struct(index) => panic!("Field '{}' is a {}, access one of its fields, line {}, column {}",
+                    field_name, self.type_table[index].tp.name, line, column),
+            }
+        }
+    }
+
+    // `[N]` or `[]` after an element type; None when the type is not an array
+    fn parse_array_dimension(&mut self) -> Option<Option<usize>> {
+        if self.tokens.peek().unwrap().kind() != TokenType::LeftBracket {
+            return None;
+        }
+        self.tokens.next(); // consume '['
+        let len = match self.tokens.peek() {
+            Some(&&Token::Number(value, None, line, column)) => {
+                self.tokens.next();
+                if value == 0 || value > u32::MAX as u128 {
+                    panic!("Array length {} out of range at line {}, column {}", value, line, column);
+                }
+                Some(value as usize)
+            }
+            _ => None,
+        };
+        self.expect(Token::RightBracket(self.last_expect_line,self.last_expect_column));
+        Some(len)
+    }
     
-    fn parse_func_decl(&mut self) {
-        self.tokens.next(); // consume 'function'
-        if let Some(Token::Identifier(name,line,column)) = self.tokens.next() {
-            self.symbol_table.push(Symbol{id: name.clone(), tp: Token::Function(*line,*column)});
+    fn parse_func_decl(&mut self) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume 'function'
+        if self.current_function().is_some() {
+            panic!("Functions must be declared at top level, line {}", line);
+        }
+        if let Some(&Token::Identifier(name,line,_)) = self.tokens.next() {
+            // the entry collect_functions made moves here, keeping the
+            // table in declaration order
+            let entry = self.symbol_table.iter().rposition(|symbol| match symbol.kind {
+                SymbolKind::Function(_, _) => symbol.id == name,
+                _ => false,
+            });
+            self.symbol_table.remove(entry.unwrap());
+            let entry = self.symbol_table.len();
+            self.symbol_table.push(SymbolEntry{id: name, kind: SymbolKind::Function(Vec::new(), None), doc: self.pending_doc.take(), assigned: true, in_scope: true, line, binding: None});
+            let outer = self.begin_scope();
             self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
-            self.parse_param_list();
+            self.local_slots = Some(0);
+            let (params, kinds) = self.parse_param_list();
             self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+            let ret = self.parse_return_type();
+            self.symbol_table[entry].kind = SymbolKind::Function(kinds, ret);
             self.expect(Token::LeftBraces(self.last_expect_line,self.last_expect_column));
-            while self.tokens.peek().unwrap().kind() != TokenType::RightBraces {
-                self.on_function = true;
-                self.parse_statement();
-                self.on_function = false;
+            self.contexts.push(Context::Function(name, ret));
+            self.locals_start = entry + 1;
+            let mut body = Vec::new();
+            while !self.closes(TokenType::RightBraces) {
+                body.push(self.parse_statement());
+            }
+            self.contexts.pop();
+            self.locals_start = 0;
+            self.local_slots = None;
+            self.end_scope(outer);
+            if ret.is_some() && !self.ast.always_returns(&body) {
+                panic!("Function '{}' does not return a value on every path, line {}", name, line);
             }
             self.expect(Token::RightBraces(self.last_expect_line,self.last_expect_column));
+            self.ast.add(NodeKind::FuncDecl{name, params, ret, body}, line)
         } else {
             panic!("Expected identifier after 'function'");
         }
     }
 
-    fn parse_param_list(&mut self) {
-        println!("PARSE");
-        while let Some(token) = self.tokens.peek() {
-            match token {
-                Token::B1(_,_) | Token::B2(_,_) | Token::B4(_,_) | Token::B8(_,_) 
-                | Token::B16(_,_) | Token::B32(_,_) | Token::B64(_,_) 
-                | Token::B128(_,_) => {
-                    self.tokens.next(); // consume type
-                    if let Some(Token::Identifier(_,_,_)) = self.tokens.next() {
-                        if self.tokens.peek().expect("LMAO").kind() == TokenType::Comma {
-                            println!("COMMA CONSUMED");
-                            self.tokens.next(); // consume ','
-                        }
-                    } else {
-                        panic!("Expected identifier in parameter list");
+    // `-> bN` after the parameter list. Functions without one return nothing.
+    fn parse_return_type(&mut self) -> Option<Type> {
+        if self.tokens.peek().unwrap().kind() != TokenType::Arrow {
+            return None;
+        }
+        self.tokens.next(); // consume '->'
+        match self.tokens.next() {
+            Some(token) if Type::from_token(token).is_some() => Type::from_token(token),
+            token => panic!("Expected a return type after '->', found {:?}", token),
+        }
+    }
+
+    // Array parameters refer to the caller's array, they are not copied
+    fn parse_param_list(&mut self) -> (Vec<NodeId>, Vec<SymbolKind>) {
+        let mut params = Vec::new();
+        let mut kinds = Vec::new();
+        while let Some((name, line, kind)) = self.parse_param() {
+            let node = match kind {
+                SymbolKind::Variable(tp) => NodeKind::Param{name, tp},
+                SymbolKind::Array(tp, len) => NodeKind::ArrayParam{name, tp, len},
+                SymbolKind::Struct(struct_name) => {
+                    if self.lookup_type(struct_name).is_none() {
+                        panic!("Unknown type '{}' of parameter '{}' at line {}", struct_name, name, line);
                     }
+                    NodeKind::StructParam{name, struct_name}
                 }
-                _ => break,
+                kind => panic!("Unexpected parameter of kind {}", kind),
+            };
+            self.declare(name, kind.clone(), None, true, line);
+            let node = self.ast.add(node, line);
+            self.resolve(node, name);
+            params.push(node);
+            kinds.push(kind);
+        }
+        (params, kinds)
+    }
+
+    // Name, line and kind of the next parameter, None after the last one.
+    // Struct types are only named here, the caller checks they exist.
+    fn parse_param(&mut self) -> Option<(Symbol, usize, SymbolKind)> {
+        let kind = match self.tokens.peek() {
+            Some(&&Token::Identifier(struct_name,_,_)) => {
+                self.tokens.next(); // consume type name
+                SymbolKind::Struct(struct_name)
+            }
+            Some(&token) if Type::from_token(token).is_some() => {
+                self.tokens.next(); // consume type
+                let tp = Type::from_token(token).unwrap();
+                match self.parse_array_dimension() {
+                    None => SymbolKind::Variable(tp),
+                    Some(len) => SymbolKind::Array(tp, len),
+                }
+            }
+            _ => return None,
+        };
+        let (name, line) = match self.tokens.next() {
+            Some(&Token::Identifier(name,line,_)) => (name, line),
+            _ => panic!("Expected identifier in parameter list"),
+        };
+        if self.tokens.peek().expect("LMAO").kind() == TokenType::Comma {
+            self.tokens.next(); // consume ','
+        }
+        Some((name, line, kind))
+    }
+    // Which symbols have been assigned on every path so far, to be restored
+    // or merged when control flow splits
+    fn assigned(&self) -> Vec<bool> {
+        self.symbol_table.iter().map(|symbol| symbol.assigned).collect()
+    }
+
+    fn set_assigned(&mut self, assigned: &[bool]) {
+        for (symbol, &assigned) in self.symbol_table.iter_mut().zip(assigned) {
+            symbol.assigned = assigned;
+        }
+    }
+
+    fn mark_assigned(&mut self, identifier: Symbol) {
+        if let Some(index) = self.lookup_index(identifier) {
+            if index >= self.locals_start {
+                self.symbol_table[index].assigned = true;
             }
         }
     }
-    fn symbol_table_contains(&self, identifier: &str) -> bool {
-        self.symbol_table.iter().any(|symbol| symbol.id == identifier)
+
+    // A branch that returns, breaks or continues never reaches the code after
+    // it, so it does not count when merging what was assigned
+    fn leaves_block(&self, body: &[NodeId]) -> bool {
+        self.ast.always_returns(body)
+            || matches!(body.last().map(|&id| &self.ast.get(id).kind), Some(&NodeKind::Break(_)) | Some(&NodeKind::Continue(_)))
+    }
+
+    fn symbol_table_contains(&self, identifier: Symbol) -> bool {
+        self.lookup_index(identifier).is_some()
+    }
+
+    fn lookup_symbol(&self, identifier: Symbol) -> Option<&SymbolEntry> {
+        self.lookup_index(identifier).map(|index| &self.symbol_table[index])
+    }
+
+    fn lookup_index(&self, identifier: Symbol) -> Option<usize> {
+        self.symbol_table.iter().rposition(|symbol| symbol.in_scope && symbol.id == identifier)
+    }
+
+    // Puts a variable or constant in the symbol table with a slot of its
+    // own, in the frame of the function being parsed or among the globals
+    fn declare(&mut self, id: Symbol, kind: SymbolKind, doc: Option<String>, assigned: bool, line: usize) {
+        if self.local_slots.is_none() {
+            self.check_global_name(id, line);
+        }
+        let earlier = self.symbol_table[self.scope_start..].iter()
+            .find(|symbol| symbol.in_scope && symbol.id == id && symbol.binding.is_some());
+        if let Some(earlier) = earlier {
+            panic!("'{}' at line {} is already declared in this scope, at line {}", id, line, earlier.line);
+        }
+        let binding = match self.local_slots {
+            Some(ref mut slots) => {
+                *slots += 1;
+                Binding::Local(*slots - 1)
+            }
+            None => {
+                self.global_slots += 1;
+                Binding::Global(self.global_slots - 1)
+            }
+        };
+        self.symbol_table.push(SymbolEntry{id, kind, doc, assigned, in_scope: true, line, binding: Some(binding)});
+    }
+
+    // Globals, constants, struct types and functions share one namespace.
+    // Functions are all collected before anything else is declared, so
+    // checking each global or struct against what is known so far covers
+    // every pair, though not always in source order.
+    fn check_global_name(&self, name: Symbol, line: usize) {
+        if prelude::intrinsic(name.as_str()).is_some() {
+            panic!("'{}' is built in and cannot be declared again, line {}", name, line);
+        }
+        let other = self.lookup_symbol(name).map(|symbol| symbol.line)
+            .or_else(|| self.lookup_type(name).map(|index| self.type_table[index].line));
+        if let Some(other) = other {
+            panic!("'{}' is declared more than once at top level, at lines {} and {}",
+                name, other.min(line), other.max(line));
+        }
+    }
+
+    // Records on `node` the slot of the variable `name` refers to where the
+    // node is
+    fn resolve(&mut self, node: NodeId, name: Symbol) {
+        if let Some(binding) = self.lookup_symbol(name).and_then(|symbol| symbol.binding) {
+            self.ast.set_binding(node, binding);
+        }
+    }
+
+    fn parse_assign_or_func_call(&mut self) -> NodeId {
+        let node = self.parse_assignment_or_call();
+        self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        node
     }
 
-    fn parse_assign_or_func_call(&mut self) {
-        self.check_symbol_table_for_identifier();
+    // An assignment or a call, without the `;`, which the step of a `for`
+    // loop does not have
+    fn parse_assignment_or_call(&mut self) -> NodeId {
+        let (name, line) = self.check_symbol_table_for_identifier();
+        let kind = self.lookup_symbol(name).unwrap().kind.clone();
         match self.tokens.peek() {
-            Some(Token::Assing(_,_)) => {
-                self.tokens.next(); // consume '='
-                if let Some(Token::Identifier(_,_,_)) = self.tokens.peek(){
-                    if self.check_symbol_table_for_type(Token::Function(self.last_expect_line,self.last_expect_column)){
-                        //println!("beenhere: {:?}", self.tokens.peek());
-                        self.parse_func_call();
-                    }
-                } else {
-                    self.parse_expression();
-                    self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
-                }
+            Some(Token::Dot(_,_)) => {
+                let (path, tp, slot) = self.parse_field_path(name);
+                let op = match self.tokens.next() {
+                    Some(op @ Token::Assing(_,_)) => op,
+                    token => panic!("Expected '=' after field, found {:?}", token),
+                };
+                let value = self.parse_assigned_value(tp, op);
+                let node = self.ast.add(NodeKind::FieldAssign{target: name, path, slot, tp, value}, line);
+                self.resolve(node, name);
+                node
             }
-            Some(Token::LeftParenthesis(_,_)) => {
-                self.tokens.next(); // consume '('
-                self.parse_func_call_param_list();
-                self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
-                self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+            Some(Token::LeftBracket(_,_)) => {
+                let (target, index) = self.parse_index(name);
+                let op = match self.tokens.next() {
+                    Some(op @ Token::Assing(_,_)) => op,
+                    token => panic!("Expected '=' after array element, found {:?}", token),
+                };
+                let value = self.parse_assigned_value(target, op);
+                let node = self.ast.add(NodeKind::IndexAssign{target: name, index, value}, line);
+                self.resolve(node, name);
+                node
             }
+            Some(&op @ Token::Assing(_,_)) => {
+                self.tokens.next(); // consume '='
+                let target = match kind {
+                    SymbolKind::Variable(tp) => tp,
+                    SymbolKind::Constant(_, _) => panic!("Cannot assign to constant '{}' at line {}, column {}",
+                        name, op.position().0, op.position().1),
+                    SymbolKind::Array(_, _) => panic!("Cannot assign to array '{}' as a whole at line {}, column {}",
+                        name, op.position().0, op.position().1),
+                    SymbolKind::Struct(_) => panic!("Cannot assign to struct '{}' as a whole at line {}, column {}, assign its fields",
+                        name, op.position().0, op.position().1),
+                    SymbolKind::Function(_, _) => panic!("Cannot assign to a function at line {}, column {}",
+                        op.position().0, op.position().1),
+                };
+                let value = self.parse_assigned_value(target, op);
+                self.mark_assigned(name);
+                let node = self.ast.add(NodeKind::Assign{target: name, value}, line);
+                self.resolve(node, name);
+                node
+            }
+            Some(Token::LeftParenthesis(_,_)) => self.parse_func_call(name, line),
             _ => panic!("Unexpected token after identifier: {:?}", self.tokens.peek()),
         }
     }
 
-    fn parse_func_call(&mut self) {
+    // Right-hand side of an assignment
+    fn parse_assigned_value(&mut self, target: Type, op: &Token) -> NodeId {
+        let value = self.parse_expression();
+        value.check_assignable(target, op);
+        value.node
+    }
+
+    // `name[index]` up to the closing bracket, returning the element type and
+    // the index expression. Constant indices are checked against the length
+    // here, the others when the program runs.
+    fn parse_index(&mut self, name: Symbol) -> (Type, NodeId) {
+        let (tp, len) = match self.lookup_symbol(name).map(|symbol| &symbol.kind) {
+            Some(&SymbolKind::Array(tp, len)) => (tp, len),
+            _ => panic!("'{}' is not an array, line {}", name, self.tokens.peek().unwrap().position().0),
+        };
+        let (line, column) = self.tokens.next().unwrap().position(); // consume '['
+        let index = self.parse_expression();
+        if index.tp != Type::Literal && index.tp.is_signed() {
+            panic!("Array index must be unsigned, found {} at line {}, column {}", index.tp, line, column);
+        }
+        if let (Some(value), Some(len)) = (index.constant, len) {
+            let out_of_bounds = if index.tp == Type::Literal {
+                value < 0 || value >= len as i128
+            } else {
+                value as u128 >= len as u128
+            };
+            if out_of_bounds {
+                panic!("Index {} out of bounds for array '{}' of length {} at line {}, column {}",
+                    value, name, len, line, column);
+            }
+        }
+        self.expect(Token::RightBracket(self.last_expect_line,self.last_expect_column));
+        (tp, index.node)
+    }
+
+    // `callee(args)`, without the `;` when it is a statement. The node has
+    // the return type of the callee, if it has one. Calls of eprint and
+    // eprintln become print nodes.
+    fn parse_func_call(&mut self, callee: Symbol, line: usize) -> NodeId {
+            let (params, ret) = match self.lookup_symbol(callee).map(|symbol| &symbol.kind) {
+                Some(&SymbolKind::Function(ref params, ret)) => (params.clone(), ret),
+                _ => panic!("'{}' is not a function, line {}", callee, line),
+            };
+            match prelude::intrinsic(callee.as_str()).map(|intrinsic| intrinsic.builtin) {
+                Some(Builtin::Eprint) => return self.parse_print_args(line, false, true),
+                Some(Builtin::Eprintln) => return self.parse_print_args(line, true, true),
+                _ => {}
+            }
             self.tokens.next(); // consume '('
-            self.parse_func_call_param_list();
+            let args = self.parse_func_call_param_list(callee, &params);
             self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
-            self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+            let call = self.ast.add(NodeKind::Call{callee, args}, line);
+            if let Some(ret) = ret {
+                self.ast.set_type(call, ret);
+            }
+            call
     }
    
-    fn parse_func_call_param_list(&mut self) {
-        //self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
-        while let Some(token) = self.tokens.peek() {
-            println!("beenhere: {:?}", self.tokens.peek());
+    // Arguments are checked against the parameters like assignments, except
+    // that arrays are passed by reference and must match the element type
+    // and, unless the parameter leaves it open, the length. Integer
+    // arguments can be any expression; arrays and structs are passed by
+    // name. Arguments are evaluated left to right.
+    fn parse_func_call_param_list(&mut self, callee: Symbol, params: &[SymbolKind]) -> Vec<NodeId> {
+        let mut args = Vec::new();
+        while let Some(&token) = self.tokens.peek() {
             if let Some(Token::RightParenthesis(_,_)) = self.tokens.peek() {
                 break;
-            } else if let Some(Token::Identifier(_,_,_)) = self.tokens.peek() {
-                self.check_symbol_table_for_identifier();
-                if self.tokens.peek().unwrap().kind() == TokenType::Comma {
-                    self.tokens.next(); // consume ','
+            }
+            let node = match params.get(args.len()) {
+                None => panic!("Too many arguments to '{}' at line {}", callee, token.position().0),
+                Some(&SymbolKind::Variable(param)) => {
+                    let arg = self.parse_expression();
+                    arg.check_assignable(param, token);
+                    arg.node
                 }
+                Some(param) => {
+                    let (name, line) = self.check_symbol_table_for_identifier();
+                    let node = self.ast.add(NodeKind::Identifier(name), line);
+                    self.resolve(node, name);
+                    match (param, &self.lookup_symbol(name).unwrap().kind) {
+                        (&SymbolKind::Array(param, param_len), &SymbolKind::Array(tp, len))
+                            if param == tp && (param_len.is_none() || param_len == len) => self.ast.set_type(node, tp),
+                        (&SymbolKind::Struct(param), &SymbolKind::Struct(struct_name)) if param == struct_name => {}
+                        (param, kind) => panic!("Argument '{}' of '{}' at line {} is {}, expected {}",
+                            name, callee, line, kind, param),
+                    }
+                    node
+                }
+            };
+            args.push(node);
+            match self.tokens.peek() {
+                Some(Token::Comma(_,_)) => { self.tokens.next(); } // consume ','
+                Some(Token::RightParenthesis(_,_)) => {}
+                token => panic!("Expected ',' or ')' after argument to '{}', found {:?}", callee, token),
+            }
+        }
+        if args.len() < params.len() {
+            panic!("Too few arguments to '{}', expected {} but found {}", callee, params.len(), args.len());
+        }
+        args
+    }
+
+    fn parse_if_stmt(&mut self) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume if
+        self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
+        let condition = self.parse_expression().node;
+        self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+        let before = self.assigned();
+        let then_body = self.parse_block();
+        let after_then = self.assigned();
+        self.set_assigned(&before);
+        let mut else_body = Vec::new();
+        if self.tokens.peek().unwrap().kind() == TokenType::Else {
+            self.tokens.next();
+            if self.tokens.peek().unwrap().kind() == TokenType::If {
+                // `else if` is an if statement making up the whole else branch
+                else_body.push(self.parse_if_stmt());
             } else {
-                panic!("Expected identifier in parameter list");
+                else_body = self.parse_block();
             }
         }
+        // assigned after the if: by both branches, or by the one that goes on
+        let after_else = self.assigned();
+        let merged: Vec<bool> = match (self.leaves_block(&then_body), self.leaves_block(&else_body)) {
+            (true, false) => after_else,
+            (false, true) => after_then,
+            (true, true) => before,
+            (false, false) => after_then.iter().zip(&after_else).map(|(&then, &els)| then && els).collect(),
+        };
+        self.set_assigned(&merged);
+        self.ast.add(NodeKind::If{condition, then_body, else_body}, line)
     }
 
-    fn parse_if_stmt(&mut self) {
-        self.tokens.next(); // consume if
+    // `match (value) { 0 => { } 1 | 2 => { } 3..=9 => { } _ => { } }`, arms
+    // optionally separated by commas. Patterns are constants of the value's
+    // type, no value may be matched by two arms, and the arms have to cover
+    // every value of the type unless a last `_` arm takes the rest.
+    fn parse_match_stmt(&mut self) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume match
         self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
-        self.parse_expression();
+        let scrutinee = self.parse_expression();
         self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
         self.expect(Token::LeftBraces(self.last_expect_line,self.last_expect_column));
-        while self.tokens.peek() != Some(&&Token::RightBraces(self.last_expect_line,self.last_expect_column)) {
-            self.parse_statement();
+        let tp = scrutinee.tp;
+        let before = self.assigned();
+        // assigned after the match: by every arm that goes on past it
+        let mut after: Option<Vec<bool>> = None;
+        let mut arms = Vec::new();
+        let mut ranges: Vec<(u128, u128, usize)> = Vec::new();
+        let mut default = None;
+        while !self.closes(TokenType::RightBraces) {
+            let arm_line = self.tokens.peek().unwrap().position().0;
+            if let Some(default) = default {
+                panic!("Arm at line {} is unreachable, the `_` arm at line {} matches every value left",
+                    arm_line, self.ast.get(arms[default]).line);
+            }
+            let mut patterns = Vec::new();
+            if let Some(&&Token::Identifier(name,_,_)) = self.tokens.peek() {
+                if name.as_str() == "_" {
+                    self.tokens.next(); // consume '_'
+                    default = Some(arms.len());
+                }
+            }
+            while default.is_none() {
+                let (start, end) = self.parse_pattern(tp);
+                let (start_key, end_key) = (tp.order_key(start), tp.order_key(end));
+                for &(other_start, other_end, arm) in ranges.iter() {
+                    if start_key <= other_end && other_start <= end_key {
+                        panic!("Pattern {} at line {} overlaps a pattern of the arm at line {}",
+                            pattern_string(tp, start_key, end_key), arm_line, self.ast.get(arms[arm]).line);
+                    }
+                }
+                ranges.push((start_key, end_key, arms.len()));
+                patterns.push((start, end));
+                if self.tokens.peek().unwrap().kind() != TokenType::Pipe {
+                    break;
+                }
+                self.tokens.next(); // consume '|'
+            }
+            self.expect(Token::FatArrow(self.last_expect_line,self.last_expect_column));
+            let body = self.parse_block();
+            if let Some(Token::Comma(_,_)) = self.tokens.peek() {
+                self.tokens.next(); // consume ','
+            }
+            if !self.leaves_block(&body) {
+                let assigned = self.assigned();
+                after = Some(match after {
+                    Some(after) => after.iter().zip(&assigned).map(|(&a, &b)| a && b).collect(),
+                    None => assigned,
+                });
+            }
+            self.set_assigned(&before);
+            arms.push(self.ast.add(NodeKind::MatchArm{patterns, body}, arm_line));
         }
         self.expect(Token::RightBraces(self.last_expect_line,self.last_expect_column));
-        if self.tokens.peek() == Some(&&Token::Else(self.last_expect_line,self.last_expect_column)) {
-            self.tokens.next();
-            self.expect(Token::LeftBraces(self.last_expect_line,self.last_expect_column));
-            while self.tokens.peek() != Some(&&Token::RightBraces(self.last_expect_line,self.last_expect_column)) {
-                self.parse_statement();
+        if default.is_none() {
+            if let Some((start, end)) = uncovered(tp, &ranges) {
+                panic!("Match on {} at line {} does not cover {}, add arms for it or a `_` arm",
+                    tp, line, pattern_string(tp, start, end));
             }
-            self.expect(Token::RightBraces(self.last_expect_line,self.last_expect_column));
+        }
+        self.set_assigned(&after.unwrap_or(before));
+        let dispatch = Dispatch::new(ranges);
+        self.ast.add(NodeKind::Match{scrutinee: scrutinee.node, arms, default, dispatch}, line)
+    }
+
+    // A constant or an inclusive range of constants, `3..=9`
+    fn parse_pattern(&mut self, tp: Type) -> (i128, i128) {
+        let start = self.parse_pattern_value(tp);
+        if self.tokens.peek().unwrap().kind() != TokenType::DotDotEq {
+            return (start, start);
+        }
+        let (line, column) = self.tokens.next().unwrap().position(); // consume '..='
+        let end = self.parse_pattern_value(tp);
+        if tp.order_key(start) > tp.order_key(end) {
+            panic!("Range {}..={} is empty at line {}, column {}",
+                tp.key_to_string(tp.order_key(start)), tp.key_to_string(tp.order_key(end)), line, column);
+        }
+        (start, end)
+    }
 
+    fn parse_pattern_value(&mut self, tp: Type) -> i128 {
+        let token = *self.tokens.peek().unwrap();
+        let value = self.parse_shift_expr();
+        value.check_assignable(tp, token);
+        match value.constant {
+            Some(constant) => tp.truncate(constant),
+            None => {
+                let (line, column) = token.position();
+                panic!("Match patterns must be constants, line {}, column {}", line, column);
+            }
         }
     }
 
-    fn parse_while_stmt(&mut self) {
-        self.tokens.next(); // consume while
+    fn parse_while_stmt(&mut self, label: Option<Symbol>) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume while
         self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
-        self.parse_expression();
+        let condition = self.parse_expression().node;
         self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+        // the body may not run at all, so what it assigns does not count after
+        // the loop
+        let before = self.assigned();
+        let (body, breaks) = self.parse_loop_body(label);
+        self.set_assigned_after_loop(Some(condition), &before, breaks);
+        self.ast.add(NodeKind::While{condition, body, label}, line)
+    }
+
+    // `for (init; condition; step) { }`, where any of the three may be left
+    // out. The init is a declaration or an assignment and the step an
+    // assignment or a call. The step runs after every pass, `continue`
+    // included.
+    fn parse_for_stmt(&mut self, label: Option<Symbol>) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume for
+        self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
+        // a variable declared by the init belongs to the loop
+        let outer = self.begin_scope();
+        let token = *self.tokens.peek().unwrap();
+        let init = match token.kind() {
+            TokenType::Semicolon => {
+                self.tokens.next(); // consume ';'
+                None
+            }
+            TokenType::Identifier => Some(self.parse_assign_or_func_call()),
+            _ if Type::from_token(token).is_some() => Some(self.parse_var_decl()),
+            _ => panic!("Expected a declaration or an assignment to start the for loop, found {:?}", token),
+        };
+        let condition = if self.tokens.peek().unwrap().kind() == TokenType::Semicolon {
+            None
+        } else {
+            Some(self.parse_expression().node)
+        };
+        self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        let step = if self.tokens.peek().unwrap().kind() == TokenType::RightParenthesis {
+            None
+        } else {
+            Some(self.parse_assignment_or_call())
+        };
+        self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+        let before = self.assigned();
+        let (body, breaks) = self.parse_loop_body(label);
+        self.set_assigned_after_loop(condition, &before, breaks);
+        self.end_scope(outer);
+        self.ast.add(NodeKind::For{init, condition, step, body, label}, line)
+    }
+
+    // `do { } while (condition);` checks the condition after each pass, so
+    // the body always runs once
+    fn parse_do_while_stmt(&mut self, label: Option<Symbol>) -> NodeId {
+        let line = self.tokens.next().unwrap().position().0; // consume do
+        let before = self.assigned();
+        let (body, _) = self.parse_loop_body(label);
+        // a jump may skip the rest of the body
+        if self.jumps(&body) {
+            self.set_assigned(&before);
+        }
+        self.expect(Token::While(self.last_expect_line,self.last_expect_column));
+        self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
+        let condition = self.parse_expression().node;
+        self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+        self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        self.ast.add(NodeKind::DoWhile{body, condition, label}, line)
+    }
+
+    // Also returns what is assigned at every break out of the loop
+    fn parse_loop_body(&mut self, label: Option<Symbol>) -> (Vec<NodeId>, Option<Vec<bool>>) {
+        self.contexts.push(Context::Loop(label));
+        self.loop_breaks.push(None);
+        let body = self.parse_block();
+        self.contexts.pop();
+        (body, self.loop_breaks.pop().unwrap())
+    }
+
+    // A loop whose condition is `true` or missing only ends at a `break`, so
+    // after it what every break had assigned is assigned. Otherwise the body
+    // may not run at all.
+    fn set_assigned_after_loop(&mut self, condition: Option<NodeId>, before: &[bool], breaks: Option<Vec<bool>>) {
+        match breaks {
+            Some(at_breaks) if self.ast.is_true(condition) => self.set_assigned(&at_breaks),
+            _ => self.set_assigned(before),
+        }
+    }
+
+    // `{ statements }`. What is declared inside goes out of scope at the
+    // closing brace.
+    fn parse_block(&mut self) -> Vec<NodeId> {
         self.expect(Token::LeftBraces(self.last_expect_line,self.last_expect_column));
-        while self.tokens.peek() != Some(&&Token::RightBraces(self.last_expect_line,self.last_expect_column)) {
-            self.on_while = true;
-            self.parse_statement();
-            self.on_while = false;
+        let outer = self.begin_scope();
+        let mut body = Vec::new();
+        while !self.closes(TokenType::RightBraces) {
+            body.push(self.parse_statement());
         }
         self.expect(Token::RightBraces(self.last_expect_line,self.last_expect_column));
+        self.end_scope(outer);
+        body
+    }
+
+    // Symbols declared from here on belong to a new scope. Returns the start
+    // of the enclosing one, to hand back to end_scope.
+    fn begin_scope(&mut self) -> usize {
+        std::mem::replace(&mut self.scope_start, self.symbol_table.len())
     }
 
-    fn parse_print_stmt(&mut self) {
-        self.tokens.next(); // consume print
+    // Symbols of the scope that has ended go out of scope
+    fn end_scope(&mut self, outer: usize) {
+        for symbol in self.symbol_table[self.scope_start..].iter_mut() {
+            symbol.in_scope = false;
+        }
+        self.scope_start = outer;
+    }
+
+    // Whether `body` has a `break` or `continue` anywhere in it
+    fn jumps(&self, body: &[NodeId]) -> bool {
+        body.iter().any(|&id| match self.ast.get(id).kind {
+            NodeKind::Break(_) | NodeKind::Continue(_) => true,
+            NodeKind::If { ref then_body, ref else_body, .. } => self.jumps(then_body) || self.jumps(else_body),
+            NodeKind::Match { ref arms, .. } => arms.iter().any(|&arm| self.jumps(self.ast.arm_body(arm))),
+            NodeKind::While { ref body, .. } | NodeKind::For { ref body, .. } | NodeKind::DoWhile { ref body, .. } => {
+                self.jumps(body)
+            }
+            _ => false,
+        })
+    }
+
+    // A name and a colon in front of a loop: `outer: while (..) { }`
+    fn label_ahead(&self) -> bool {
+        let mut lookahead = self.tokens.clone();
+        matches!((lookahead.next(), lookahead.next()), (Some(Token::Identifier(_,_,_)), Some(Token::Colon(_,_))))
+    }
+
+    // `break label;` and `continue label;` refer to the loop by its label
+    // from any loop nested in it
+    fn parse_labeled_loop(&mut self) -> NodeId {
+        let (label, line, column) = match self.tokens.next() {
+            Some(&Token::Identifier(label, line, column)) => (label, line, column),
+            token => panic!("Expected a label, found {:?}", token),
+        };
+        self.tokens.next(); // consume ':'
+        if self.enclosing_loops().any(|enclosing| enclosing == Some(label)) {
+            panic!("Label '{}' is already used by an enclosing loop, line {}, column {}", label, line, column);
+        }
+        match self.tokens.peek() {
+            Some(Token::While(_,_)) => self.parse_while_stmt(Some(label)),
+            Some(Token::For(_,_)) => self.parse_for_stmt(Some(label)),
+            Some(Token::Do(_,_)) => self.parse_do_while_stmt(Some(label)),
+            token => panic!("Expected a loop after label '{}', found {:?}", label, token),
+        }
+    }
+
+    // `print(value)`, or a format string followed by one argument for each of
+    // its placeholders: `print("sum = {}, hex = {:x}\n", sum, sum)`.
+    // `println` adds a newline at the end and may be called with nothing.
+    fn parse_print_stmt(&mut self) -> NodeId {
+        let token = self.tokens.next().unwrap(); // consume print or println
+        let node = self.parse_print_args(token.position().0, token.kind() == TokenType::Println, false);
+        self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        node
+    }
+
+    // The parenthesized arguments of print and println, and of the prelude's
+    // eprint and eprintln, which write to the standard error
+    fn parse_print_args(&mut self, line: usize, newline: bool, stderr: bool) -> NodeId {
         self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
-        self.parse_expression();
+        let (format, pieces, args) = match self.tokens.peek() {
+            Some(&&Token::StringLiteral(format, line, column)) => {
+                self.tokens.next(); // consume format string
+                let pieces = parse_format(format.as_str(), line, column);
+                let mut args = Vec::new();
+                while self.tokens.peek().unwrap().kind() == TokenType::Comma {
+                    self.tokens.next(); // consume ','
+                    args.push(self.parse_expression());
+                }
+                self.check_format_args(&pieces, &args, line, column);
+                (format, pieces, args.iter().map(|arg| arg.node).collect())
+            }
+            Some(&&Token::RightParenthesis(_, _)) if newline => (Symbol::intern(""), Vec::new(), Vec::new()),
+            _ => {
+                let value = self.parse_expression();
+                (Symbol::intern("{}"), vec![FormatPiece::Argument(FormatSpec::decimal())], vec![value.node])
+            }
+        };
         self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
-        self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        self.ast.add(NodeKind::Print{format, pieces, args, newline, stderr}, line)
+    }
+
+    // One argument per placeholder, of a type the placeholder can show. A
+    // width is only the least number of characters printed, so it fits any
+    // type. Characters take unsigned values of at most 32 bits, wide enough
+    // for every code point; a literal has to be a code point itself.
+    fn check_format_args(&self, pieces: &[FormatPiece], args: &[Expr], line: usize, column: Column) {
+        let specs: Vec<FormatSpec> = pieces.iter().filter_map(|piece| match *piece {
+            FormatPiece::Argument(spec) => Some(spec),
+            FormatPiece::Text(_) => None,
+        }).collect();
+        if specs.len() != args.len() {
+            panic!("Format string at line {}, column {} has {} placeholders but {} arguments were given",
+                line, column, specs.len(), args.len());
+        }
+        for (index, (spec, arg)) in specs.iter().zip(args).enumerate() {
+            if spec.kind != 'c' {
+                continue;
+            }
+            match (arg.tp, arg.constant) {
+                (Type::Unsigned(width), _) if width <= 32 => {}
+                (Type::Literal, Some(value)) if (0..=u32::MAX as i128).contains(&value) && char::from_u32(value as u32).is_some() => {}
+                (Type::Literal, value) => panic!("Argument {} of the format string at line {}, column {} is {}, not a character code point",
+                    index + 1, line, column, value.unwrap_or_default()),
+                (tp, _) => panic!("Argument {} of the format string at line {}, column {} is a {}, but {{:c}} takes an unsigned value of at most 32 bits, convert it with `as b32`",
+                    index + 1, line, column, tp),
+            }
+        }
     }
 
-    fn parse_break_stmt(&mut self) {
-        self.tokens.next(); // consume break
+    fn parse_break_stmt(&mut self) -> NodeId {
+        let (line, column) = self.tokens.next().unwrap().position(); // consume break
+        let label = self.parse_jump_label("break", line, column);
         self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        let depth = self.enclosing_loops().position(|enclosing| label.is_none() || enclosing == label).unwrap();
+        let target = self.loop_breaks.len() - 1 - depth;
+        let assigned = self.assigned();
+        self.loop_breaks[target] = Some(match self.loop_breaks[target].take() {
+            Some(earlier) => earlier.iter().zip(&assigned).map(|(&earlier, &now)| earlier && now).collect(),
+            None => assigned,
+        });
+        self.ast.add(NodeKind::Break(label), line)
     }
 
-    fn parse_continue_stmt(&mut self) {
-        self.tokens.next(); // consume continue
+    fn parse_continue_stmt(&mut self) -> NodeId {
+        let (line, column) = self.tokens.next().unwrap().position(); // consume continue
+        let label = self.parse_jump_label("continue", line, column);
         self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        self.ast.add(NodeKind::Continue(label), line)
     }
 
-    fn parse_return_stmt(&mut self) {
-        self.tokens.next(); // consume return
-        if self.tokens.peek() != Some(&&Token::Semicolon(self.last_expect_line,self.last_expect_column)) {
-            self.parse_expression();
+    // Label after `break` or `continue`, if any, which has to name one of
+    // the loops around it
+    fn parse_jump_label(&mut self, jump: &str, line: usize, column: Column) -> Option<Symbol> {
+        if self.loop_depth() == 0 {
+            panic!("'{}' outside of a loop at line {}, column {}", jump, line, column);
+        }
+        match self.tokens.peek() {
+            Some(&&Token::Identifier(label, line, column)) => {
+                self.tokens.next(); // consume label
+                if !self.enclosing_loops().any(|enclosing| enclosing == Some(label)) {
+                    panic!("No loop labeled '{}' around '{}' at line {}, column {}", label, jump, line, column);
+                }
+                Some(label)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_return_stmt(&mut self) -> NodeId {
+        let token = self.tokens.next().unwrap(); // consume return
+        let (line, column) = token.position();
+        let (name, ret) = match self.current_function() {
+            Some(function) => function,
+            None => panic!("'return' outside of a function at line {}, column {}", line, column),
+        };
+        let mut value = None;
+        if self.tokens.peek().unwrap().kind() != TokenType::Semicolon {
+            let expr = self.parse_expression();
+            match ret {
+                Some(ret) => expr.check_assignable(ret, token),
+                None => panic!("Function '{}' does not return a value, but 'return' has one at line {}", name, line),
+            }
+            value = Some(expr.node);
+        } else if let Some(ret) = ret {
+            panic!("Function '{}' must return a {} value, 'return' has none at line {}", name, ret, line);
         }
         self.expect(Token::Semicolon(self.last_expect_line,self.last_expect_column));
+        self.ast.add(NodeKind::Return(value), line)
     }
 
-    fn parse_expression(&mut self) {
-        self.parse_arith_expr();
+    fn parse_expression(&mut self) -> Expr {
+        self.parse_or_expr()
     }
 
-    fn parse_arith_expr(&mut self) {
-        self.parse_term();
-        while let Some(&token) = self.tokens.peek() {
-            match token {
+    fn binary(&mut self, op: &Token, left: Expr, right: Expr) -> Expr {
+        let node = self.ast.add(NodeKind::Binary{op: op.kind(), left: left.node, right: right.node}, op.position().0);
+        self.typed(Expr::binary(op, left, right, node))
+    }
+
+    // Records the type of an expression on its node
+    fn typed(&mut self, expr: Expr) -> Expr {
+        self.ast.set_type(expr.node, expr.tp);
+        expr
+    }
+
+    // || and && short-circuit: the right operand is only evaluated when the
+    // left one does not already decide the result.
+    fn parse_or_expr(&mut self) -> Expr {
+        let mut left = self.parse_and_expr();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::Or(_,_) => {
+                    self.tokens.next(); // consume '||'
+                    let right = self.parse_and_expr();
+                    left = self.binary(op, left, right);
+                }
+                _ => break,
+            }
+        }
+        left
+    }
+
+    fn parse_and_expr(&mut self) -> Expr {
+        let mut left = self.parse_bit_or_expr();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::And(_,_) => {
+                    self.tokens.next(); // consume '&&'
+                    let right = self.parse_bit_or_expr();
+                    left = self.binary(op, left, right);
+                }
+                _ => break,
+            }
+        }
+        left
+    }
+
+    fn parse_bit_or_expr(&mut self) -> Expr {
+        let mut left = self.parse_bit_xor_expr();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::Pipe(_,_) => {
+                    self.tokens.next(); // consume '|'
+                    let right = self.parse_bit_xor_expr();
+                    left = self.binary(op, left, right);
+                }
+                _ => break,
+            }
+        }
+        left
+    }
+
+    fn parse_bit_xor_expr(&mut self) -> Expr {
+        let mut left = self.parse_bit_and_expr();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::Caret(_,_) => {
+                    self.tokens.next(); // consume '^'
+                    let right = self.parse_bit_and_expr();
+                    left = self.binary(op, left, right);
+                }
+                _ => break,
+            }
+        }
+        left
+    }
+
+    fn parse_bit_and_expr(&mut self) -> Expr {
+        let mut left = self.parse_rel_expr();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::Ampersand(_,_) => {
+                    self.tokens.next(); // consume '&'
+                    let right = self.parse_rel_expr();
+                    left = self.binary(op, left, right);
+                }
+                _ => break,
+            }
+        }
+        left
+    }
+
+    fn parse_rel_expr(&mut self) -> Expr {
+        let left = self.parse_shift_expr();
+        match self.tokens.peek() {
+            Some(&op @ Token::Equal(_,_)) | Some(&op @ Token::NotEqual(_,_))
+            | Some(&op @ Token::Greater(_,_)) | Some(&op @ Token::GreaterEqual(_,_))
+            | Some(&op @ Token::Less(_,_)) | Some(&op @ Token::LessEqual(_,_)) => {
+                self.tokens.next(); // consume operator
+                let right = self.parse_shift_expr();
+                self.binary(op, left, right)
+            }
+            _ => left,
+        }
+    }
+
+    fn parse_shift_expr(&mut self) -> Expr {
+        let mut left = self.parse_arith_expr();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::ShiftLeft(_,_) | Token::ShiftRight(_,_) => {
+                    self.tokens.next(); // consume operator
+                    let right = self.parse_arith_expr();
+                    left = self.binary(op, left, right);
+                }
+                _ => break,
+            }
+        }
+        left
+    }
+
+    fn parse_arith_expr(&mut self) -> Expr {
+        let mut left = self.parse_term();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
                 Token::Plus(_,_) | Token::Minus(_,_) => {
                     self.tokens.next(); // consume operator
-                    self.parse_term();
+                    let right = self.parse_term();
+                    left = self.binary(op, left, right);
                 }
-                Token::True(_,_) | Token::False(_,_) => break,
                 _ => break,
             }
         }
+        left
     }
 
-    fn parse_term(&mut self) {
-        self.parse_factor();
-        while let Some(&token) = self.tokens.peek() {
-            match token {
-                Token::Star(_,_) | Token::Slash(_,_) => {
+    fn parse_term(&mut self) -> Expr {
+        let mut left = self.parse_unary();
+        while let Some(&op) = self.tokens.peek() {
+            match op {
+                Token::Star(_,_) | Token::Slash(_,_) | Token::Percent(_,_) => {
                     self.tokens.next(); // consume operator
-                    self.parse_factor();
+                    let right = self.parse_unary();
+                    left = self.binary(op, left, right);
                 }
                 _ => break,
             }
         }
+        left
+    }
+
+    // `!` gives a b1, 1 when its operand is zero, like a comparison would
+    fn parse_unary(&mut self) -> Expr {
+        match self.tokens.peek() {
+            Some(&op @ Token::Not(_,_)) => {
+                self.tokens.next(); // consume '!'
+                let operand = self.parse_unary();
+                let node = self.ast.add(NodeKind::Unary{op: op.kind(), operand: operand.node}, op.position().0);
+                self.typed(Expr::boolean(operand.constant.map(|value| value == 0), node))
+            }
+            Some(&op @ Token::Tilde(_,_)) => {
+                self.tokens.next(); // consume '~'
+                let operand = self.parse_unary();
+                let node = self.ast.add(NodeKind::Unary{op: op.kind(), operand: operand.node}, op.position().0);
+                self.typed(Expr::new(operand.tp, operand.constant.map(|value| operand.tp.truncate(!value)), node))
+            }
+            Some(&op @ Token::Minus(_,_)) => {
+                self.tokens.next(); // consume '-'
+                if let Some(&&Token::Number(value, suffix, line, _)) = self.tokens.peek() {
+                    if let Some(tp) = Type::negated_minimum(value, suffix) {
+                        self.tokens.next(); // consume number
+                        // held as the most negative value, which is also
+                        // its own negation
+                        let node = self.ast.add(NodeKind::Number(value), line);
+                        let operand = self.typed(Expr::new(tp, Some(tp.truncate(value as i128)), node));
+                        let node = self.ast.add(NodeKind::Unary{op: op.kind(), operand: operand.node}, op.position().0);
+                        let negated = self.typed(Expr::new(operand.tp, operand.constant, node));
+                        // the cast applies to the negated value, it is the
+                        // literal that could not stand on its own
+                        return self.parse_casts(negated);
+                    }
+                }
+                let operand = self.parse_unary();
+                let node = self.ast.add(NodeKind::Unary{op: op.kind(), operand: operand.node}, op.position().0);
+                self.typed(operand.negate(op, node))
+            }
+            _ => self.parse_factor(),
+        }
+    }
+
+    fn parse_factor(&mut self) -> Expr {
+        let mut lookahead = self.tokens.clone();
+        let (indexed, field, called) = match (lookahead.next(), lookahead.next()) {
+            (Some(Token::Identifier(_,_,_)), Some(Token::LeftBracket(_,_))) => (true, false, false),
+            (Some(Token::Identifier(_,_,_)), Some(Token::Dot(_,_))) => (false, true, false),
+            (Some(Token::Identifier(_,_,_)), Some(Token::LeftParenthesis(_,_))) => (false, false, true),
+            _ => (false, false, false),
+        };
+        let expr = if called {
+            let (callee, line) = self.check_symbol_table_for_identifier();
+            let call = self.parse_func_call(callee, line);
+            match self.ast.get(call).tp {
+                Some(ret) => Expr::new(ret, None, call),
+                None => panic!("Function '{}' does not return a value and cannot be used in an expression, line {}",
+                    callee, line),
+            }
+        } else if field {
+            let (name, line) = self.check_symbol_table_for_identifier();
+            let (path, tp, slot) = self.parse_field_path(name);
+            let node = self.ast.add(NodeKind::FieldAccess{base: name, path, slot}, line);
+            self.resolve(node, name);
+            self.typed(Expr::new(tp, None, node))
+        } else if indexed {
+            let (name, line) = self.check_symbol_table_for_identifier();
+            let (tp, index) = self.parse_index(name);
+            let node = self.ast.add(NodeKind::Index{array: name, index}, line);
+            self.resolve(node, name);
+            self.typed(Expr::new(tp, None, node))
+        } else {
+            self.parse_primary()
+        };
+        self.parse_casts(expr)
+    }
+
+    fn parse_casts(&mut self, mut expr: Expr) -> Expr {
+        while let Some(Token::As(_,_)) = self.tokens.peek() {
+            self.tokens.next(); // consume 'as'
+            match self.tokens.next() {
+                Some(token) if Type::from_token(token).is_some() => {
+                    let tp = Type::from_token(token).unwrap();
+                    let node = self.ast.add(NodeKind::Convert{tp, operand: expr.node}, token.position().0);
+                    expr = self.typed(expr.convert(tp, node));
+                }
+                token => panic!("Expected type after 'as', found {:?}", token),
+            }
+        }
+        expr
     }
 
-    fn parse_factor(&mut self) {
+    fn parse_primary(&mut self) -> Expr {
         match self.tokens.next() {
-            Some(Token::Number(_,_,_)) => {}
-            Some(Token::Identifier(_,_,_)) => {}
+            Some(token @ &Token::Number(value,_,line,_)) => {
+                let node = self.ast.add(NodeKind::Number(value), line);
+                self.typed(Expr::literal(token, node))
+            }
+            Some(&Token::Identifier(name,line,column)) => {
+                let (tp, constant) = match self.lookup_index(name) {
+                    Some(index) => match self.symbol_table[index].kind {
+                        SymbolKind::Variable(tp) => {
+                            if index >= self.locals_start && !self.symbol_table[index].assigned {
+                                panic!("Variable '{}' is read before it is assigned a value at line {}, column {}",
+                                    name, line, column);
+                            }
+                            (tp, None)
+                        }
+                        SymbolKind::Constant(tp, value) => (tp, Some(value)),
+                        SymbolKind::Array(_, _) => panic!("'{}' is an array, index it as {}[..], line {}, column {}",
+                            name, name, line, column),
+                        SymbolKind::Struct(_) => panic!("'{}' is a struct, access one of its fields as {}.field, line {}, column {}",
+                            name, name, line, column),
+                        SymbolKind::Function(_, _) => panic!("'{}' is not a variable, line {}, column {}", name, line, column),
+                    },
+                    None => panic!("Identifier '{}' not declared", name),
+                };
+                let node = self.ast.add(NodeKind::Identifier(name), line);
+                self.resolve(node, name);
+                self.typed(Expr::new(tp, constant, node))
+            }
+            Some(token) if Type::from_token(token).is_some() => {
+                // conversion, written like a call to the target type: s32(x)
+                self.expect(Token::LeftParenthesis(self.last_expect_line,self.last_expect_column));
+                let expr = self.parse_expression();
+                self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+                let tp = Type::from_token(token).unwrap();
+                let node = self.ast.add(NodeKind::Convert{tp, operand: expr.node}, token.position().0);
+                self.typed(expr.convert(tp, node))
+            }
+            Some(&Token::True(line,_)) => {
+                let node = self.ast.add(NodeKind::Bool(true), line);
+                self.typed(Expr::boolean(Some(true), node))
+            }
+            Some(&Token::False(line,_)) => {
+                let node = self.ast.add(NodeKind::Bool(false), line);
+                self.typed(Expr::boolean(Some(false), node))
+            }
             Some(Token::LeftParenthesis(_,_)) => {
-                self.parse_arith_expr();
+                let expr = self.parse_expression();
                 self.expect(Token::RightParenthesis(self.last_expect_line,self.last_expect_column));
+                expr
             }
-            token => panic!("Unexpected token in factor: {:?}", token),
+            token => panic!("Unexpected token in expression: {:?}", token),
         }
     }
 
-    fn check_symbol_table_for_type(&mut self, tp: Token) -> bool {
-        self.symbol_table.iter().any(|symbol| symbol.tp == tp)
-    }
-
-    fn check_symbol_table_for_identifier(&mut self) {
-        if let Some(Token::Identifier(ref identifier, _, _)) = self.tokens.peek() {
+    // Consumes a declared identifier, returning its name and line
+    fn check_symbol_table_for_identifier(&mut self) -> (Symbol, usize) {
+        if let Some(&&Token::Identifier(identifier, line, _)) = self.tokens.peek() {
             if !self.symbol_table_contains(identifier) {
                 panic!("Identifier '{}' not declared", identifier);
             }
             self.tokens.next();
+            (identifier, line)
         } else {
             panic!("Expected identifier, but found {:?}", self.tokens.peek());
         }
@@ -308,7 +1680,6 @@ impl<'a> Parser<'a> {
     fn expect(&mut self, expected: Token) {
         match self.tokens.next() {
             Some(token) if token.kind() == expected.kind() => {
-                println!("Token expected and found: {:?}", token);
                 self.last_expect_line = token.position().0;
                 self.last_expect_column = token.position().1;
             }
@@ -329,3 +1700,168 @@ impl<'a> Parser<'a> {
         }
     }
 }
+
+// First run of values of `tp` that none of the key `ranges` cover
+fn uncovered(tp: Type, ranges: &[(u128, u128, usize)]) -> Option<(u128, u128)> {
+    let (low, high) = tp.key_bounds();
+    let mut sorted: Vec<(u128, u128)> = ranges.iter().map(|&(start, end, _)| (start, end)).collect();
+    sorted.sort();
+    let mut next = low;
+    for (start, end) in sorted {
+        if start > next {
+            return Some((next, start - 1));
+        }
+        match end.checked_add(1) {
+            Some(after) if end < high => next = next.max(after),
+            _ => return None,
+        }
+    }
+    Some((next, high))
+}
+
+fn pattern_string(tp: Type, start: u128, end: u128) -> String {
+    if start == end {
+        tp.key_to_string(start)
+    } else {
+        format!("{}..={}", tp.key_to_string(start), tp.key_to_string(end))
+    }
+}
+
+// Splits a format string around its placeholders: `{}` or `{:` followed by
+// an optional `0` to pad with zeroes, a width and one of `d`, `x`, `X`, `o`,
+// `b` and `c`, then `}`. Characters are padded with spaces only. `{{` and
+// `}}` stand for literal braces.
+fn parse_format(format: &str, line: usize, column: Column) -> Vec<FormatPiece> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(ch) => placeholder.push(ch),
+                        None => panic!("Unterminated placeholder in format string at line {}, column {}", line, column),
+                    }
+                }
+                let spec = match placeholder.as_str() {
+                    "" => Some(FormatSpec::decimal()),
+                    _ if placeholder.starts_with(':') => parse_format_spec(&placeholder[1..]),
+                    _ => None,
+                };
+                match spec {
+                    Some(spec) => {
+                        if !text.is_empty() {
+                            pieces.push(FormatPiece::Text(text.split_off(0)));
+                        }
+                        pieces.push(FormatPiece::Argument(spec));
+                    }
+                    None => panic!("Invalid placeholder '{{{}}}' in format string at line {}, column {}", placeholder, line, column),
+                }
+            }
+            '}' => panic!("Unmatched '}}' in format string at line {}, column {}, write '}}}}' for a brace", line, column),
+            _ => text.push(ch),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    pieces
+}
+
+fn parse_format_spec(spec: &str) -> Option<FormatSpec> {
+    let zero = spec.starts_with('0');
+    let digits = spec.len() - spec.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+    let width = if digits == 0 { 0 } else { spec[..digits].parse().ok()? };
+    let kind = match &spec[digits..] {
+        "" => 'd',
+        kind @ "d" | kind @ "x" | kind @ "X" | kind @ "o" | kind @ "b" | kind @ "c" => kind.chars().next().unwrap(),
+        _ => return None,
+    };
+    if zero && (width == 0 || kind == 'c') {
+        return None;
+    }
+    Some(FormatSpec { kind, zero, width })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_format, Parser};
+    use crate::compiler::{Column, Lexer};
+    use std::panic::{self, AssertUnwindSafe};
+
+    // Reference docs of the program, or the message the compiler panicked
+    // with
+    fn docs(source: &str) -> Result<String, String> {
+        let tokens: Vec<_> = Lexer::new(source).map(|token| token.unwrap()).collect();
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut parser = Parser::new(&tokens);
+            parser.parse();
+            parser.reference_docs()
+        })).map_err(|payload| match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(_) => "panicked without a message".to_string(),
+        })
+    }
+
+    #[test]
+    fn doc_comments_document_the_declaration_after_them() {
+        let source = "
+/// A point on the screen.
+struct Point {
+    /// Distance from the left edge.
+    b8 x;
+    b8 y;
+    /// Nothing follows this one.
+}
+
+/// Starts here.
+function main() {
+    /// Documents the local below it.
+    b8 i = 0;
+    /// Just a comment before a statement.
+    if (i == 0) {
+        i = 1;
+        /// Or at the end of a block.
+    }
+}
+/// Or at the end of the program.
+";
+        assert_eq!(docs(source), Ok("## Point (struct, 2 bytes)\n\nA point on the screen.\n\n\
+            - `x`: Distance from the left edge.\n\n\
+            ## main (function)\n\nStarts here.\n\n## i (b8)\n\nDocuments the local below it.\n\n".to_string()));
+    }
+
+    // The pieces of the format string written out, or the message parsing
+    // it panicked with
+    fn format(format: &str) -> Result<String, String> {
+        panic::catch_unwind(|| format!("{:?}", parse_format(format, 1, Column::default())))
+            .map_err(|payload| *payload.downcast::<String>().unwrap())
+    }
+
+    #[test]
+    fn format_strings_split_into_text_and_placeholders() {
+        assert_eq!(format("{{x}} = {:08b}{}"), Ok("[Text(\"{x} = \"), Argument(FormatSpec { kind: 'b', zero: true, width: 8 }), \
+            Argument(FormatSpec { kind: 'd', zero: false, width: 0 })]".to_string()));
+        assert_eq!(format("{:5X}}}"), Ok("[Argument(FormatSpec { kind: 'X', zero: false, width: 5 }), Text(\"}\")]".to_string()));
+    }
+
+    #[test]
+    fn invalid_placeholders_are_rejected() {
+        for spec in ["{:q}", "{x}", "{:0}", "{:05c}", "{:-5}"].iter() {
+            assert_eq!(format(spec), Err(format!("Invalid placeholder '{}' in format string at line 1, column 0", spec)));
+        }
+        assert_eq!(format("{:x"), Err("Unterminated placeholder in format string at line 1, column 0".to_string()));
+        assert_eq!(format("a } b"), Err("Unmatched '}' in format string at line 1, column 0, write '}}' for a brace".to_string()));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::compiler::types::Type;
+use std::io::{self, BufRead};
+
+// Functions every program can call without declaring them. The parser puts
+// them in the symbol table ahead of the program's own declarations, so they
+// are called and checked like any other function, and the interpreter runs
+// them in place of a body.
+#[derive(Clone, Copy, Debug)]
+pub enum Builtin {
+    ReadInt,
+    ReadLine,
+    Eof,
+    Exit,
+    Eprint,
+    Eprintln,
+}
+
+#[derive(Clone, Copy)]
+pub enum Param {
+    Int(Type),
+    // Array of the element type and any length, passed by reference
+    Buffer(Type),
+}
+
+pub struct Intrinsic {
+    pub builtin: Builtin,
+    pub name: &'static str,
+    pub params: &'static [Param],
+    pub ret: Option<Type>,
+}
+
+pub const PRELUDE: [Intrinsic; 6] = [
+    // Next integer of the input, words being separated by whitespace
+    Intrinsic { builtin: Builtin::ReadInt, name: "read_int", params: &[], ret: Some(Type::Signed(64)) },
+    // Rest of the current input line, without the line break, into the
+    // buffer. Longer lines are cut at the buffer's length. Returns the number
+    // of bytes stored, or -1 at the end of the input.
+    Intrinsic { builtin: Builtin::ReadLine, name: "read_line", params: &[Param::Buffer(Type::Unsigned(8))], ret: Some(Type::Signed(64)) },
+    // True once nothing but whitespace is left of the input
+    Intrinsic { builtin: Builtin::Eof, name: "eof", params: &[], ret: Some(Type::Unsigned(1)) },
+    // Ends the program with the status, wherever it is called from
+    Intrinsic { builtin: Builtin::Exit, name: "exit", params: &[Param::Int(Type::Signed(32))], ret: None },
+    // print and println to the standard error. They take the same format
+    // string and arguments, which the parser checks the way it does for
+    // print, so no parameters are listed.
+    Intrinsic { builtin: Builtin::Eprint, name: "eprint", params: &[], ret: None },
+    Intrinsic { builtin: Builtin::Eprintln, name: "eprintln", params: &[], ret: None },
+];
+
+pub fn intrinsic(name: &str) -> Option<&'static Intrinsic> {
+    PRELUDE.iter().find(|intrinsic| intrinsic.name == name)
+}
+
+// Standard input, read a line at a time. `read_int` takes words off the
+// current line and `read_line` whatever is left of it, so after the last
+// number on a line has been read the next `read_line` gets the line below.
+pub struct Input {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input { line: Vec::new(), pos: 0 }
+    }
+
+    // Reads the next line once the current one is used up. False at the end
+    // of the input.
+    fn fill(&mut self) -> bool {
+        if self.pos < self.line.len() {
+            return true;
+        }
+        self.line.clear();
+        self.pos = 0;
+        match io::stdin().lock().read_until(b'\n', &mut self.line) {
+            Ok(read) => read > 0,
+            Err(error) => panic!("Cannot read the standard input: {}", error),
+        }
+    }
+
+    // Moves to the next byte that is not whitespace, reading lines as needed.
+    // False if the input ends first.
+    fn skip_whitespace(&mut self) -> bool {
+        while self.fill() {
+            while self.pos < self.line.len() && self.line[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            if self.pos < self.line.len() {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn at_eof(&mut self) -> bool {
+        !self.skip_whitespace()
+    }
+
+    // The error explains what was found instead of a number
+    pub fn read_int(&mut self, tp: Type) -> Result<i128, String> {
+        if !self.skip_whitespace() {
+            return Err("reached the end of the input".to_string());
+        }
+        let start = self.pos;
+        while self.pos < self.line.len() && !self.line[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        let word = String::from_utf8_lossy(&self.line[start..self.pos]).into_owned();
+        // a line ending right after the number counts as read
+        if self.line[self.pos..].iter().all(u8::is_ascii_whitespace) {
+            self.pos = self.line.len();
+        }
+        match word.parse::<i128>() {
+            Ok(value) if tp.fits(value) => Ok(value),
+            Ok(_) => Err(format!("'{}' does not fit in {}", word, tp)),
+            Err(_) => Err(format!("'{}' is not an integer", word)),
+        }
+    }
+
+    // None at the end of the input
+    pub fn read_line(&mut self) -> Option<Vec<u8>> {
+        if !self.fill() {
+            return None;
+        }
+        let mut line = self.line[self.pos..].to_vec();
+        self.pos = self.line.len();
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        Some(line)
+    }
+}
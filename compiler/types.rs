@@ -0,0 +1,465 @@
+use crate::compiler::{Token, TokenType};
+use crate::compiler::ast::NodeId;
+use crate::compiler::interner::Symbol;
+
+// Integer types are named after their width: b8 is an 8 bit unsigned value,
+// s8 its two's complement signed counterpart.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Type {
+    Unsigned(usize),
+    Signed(usize),
+    // Integer literal, takes the width of the operand it is combined with
+    Literal,
+}
+
+// User-defined record type. Fields are laid out in declaration order, each
+// at the next offset that is a multiple of its alignment, and the size is
+// rounded up to the largest alignment so arrays of the struct stay aligned.
+// For the interpreter every integer field also gets a slot: its index when
+// the struct is flattened into a list of integers, nested structs included.
+#[derive(Debug)]
+pub struct StructType {
+    pub name: Symbol,
+    pub fields: Vec<Field>,
+    pub size: usize,
+    pub align: usize,
+    pub slots: usize,
+}
+
+// First offset at or after `offset` that is a multiple of `align`
+pub fn align_to(offset: usize, align: usize) -> usize {
+    offset.next_multiple_of(align)
+}
+
+impl StructType {
+    // `types` are the structs declared so far, which nested struct fields
+    // refer to by index.
+    pub fn layout(name: Symbol, fields: &[(Symbol, FieldType)], types: &[&StructType]) -> StructType {
+        let (mut offset, mut align, mut slot) = (0, 1, 0);
+        let mut laid_out = Vec::new();
+        for &(field_name, tp) in fields {
+            let (size, field_align, slots) = match tp {
+                FieldType::Integer(tp) => (tp.size(), tp.size(), 1),
+                FieldType::Struct(index) => (types[index].size, types[index].align, types[index].slots),
+            };
+            offset = align_to(offset, field_align);
+            laid_out.push(Field { name: field_name, tp, offset, slot });
+            offset += size;
+            slot += slots;
+            align = align.max(field_align);
+        }
+        let size = align_to(offset, align);
+        StructType { name, fields: laid_out, size, align, slots: slot }
+    }
+
+    pub fn field(&self, name: Symbol) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub name: Symbol,
+    pub tp: FieldType,
+    pub offset: usize,
+    pub slot: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Integer(Type),
+    // Index of the struct in the parser's type table
+    Struct(usize),
+}
+
+// Result of parsing an expression: its type and, when every operand is
+// known at compile time, its folded value, along with the syntax tree node
+// it was built from.
+#[derive(Debug, Clone, Copy)]
+pub struct Expr {
+    pub tp: Type,
+    pub constant: Option<i128>,
+    pub node: NodeId,
+}
+
+impl Type {
+    pub fn from_token(token: &Token) -> Option<Type> {
+        Type::from_kind(token.kind())
+    }
+
+    pub fn from_kind(kind: TokenType) -> Option<Type> {
+        match kind {
+            TokenType::B1 => Some(Type::Unsigned(1)),
+            TokenType::B2 => Some(Type::Unsigned(2)),
+            TokenType::B4 => Some(Type::Unsigned(4)),
+            TokenType::B8 => Some(Type::Unsigned(8)),
+            TokenType::B16 => Some(Type::Unsigned(16)),
+            TokenType::B32 => Some(Type::Unsigned(32)),
+            TokenType::B64 => Some(Type::Unsigned(64)),
+            TokenType::B128 => Some(Type::Unsigned(128)),
+            TokenType::S8 => Some(Type::Signed(8)),
+            TokenType::S16 => Some(Type::Signed(16)),
+            TokenType::S32 => Some(Type::Signed(32)),
+            TokenType::S64 => Some(Type::Signed(64)),
+            TokenType::S128 => Some(Type::Signed(128)),
+            _ => None,
+        }
+    }
+
+    pub fn width(&self) -> Option<usize> {
+        match self {
+            Type::Unsigned(width) | Type::Signed(width) => Some(*width),
+            Type::Literal => None,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        match self {
+            Type::Unsigned(_) => false,
+            Type::Signed(_) | Type::Literal => true,
+        }
+    }
+
+    // Operands of different widths are widened to the larger one. Signed and
+    // unsigned operands are never mixed implicitly, the program has to
+    // convert one of them first.
+    pub fn unify(left: Type, right: Type) -> Option<Type> {
+        match (left, right) {
+            (Type::Literal, tp) | (tp, Type::Literal) => Some(tp),
+            (Type::Unsigned(l), Type::Unsigned(r)) => Some(Type::Unsigned(l.max(r))),
+            (Type::Signed(l), Type::Signed(r)) => Some(Type::Signed(l.max(r))),
+            _ => None,
+        }
+    }
+
+    // Wraps a value to the width of the type, like the hardware would:
+    // unsigned values are masked, signed values are sign extended from their
+    // top bit.
+    pub fn truncate(&self, value: i128) -> i128 {
+        match self {
+            Type::Unsigned(width) if *width < 128 => value & ((1i128 << width) - 1),
+            Type::Signed(width) if *width < 128 => (value << (128 - width)) >> (128 - width),
+            _ => value,
+        }
+    }
+
+    // Bytes an integer of this type occupies in memory, which is also its
+    // alignment: widths below 8 bits still take a whole byte.
+    pub fn size(&self) -> usize {
+        self.width().unwrap_or(128).div_ceil(8)
+    }
+
+    // Maps values of the type to u128s in the same order, so ranges of
+    // values compare the same way for every type: signed values get their
+    // sign bit flipped, and b128 values above i128::MAX, stored negative,
+    // come out right when read as u128. Untyped literals order like s128.
+    pub fn order_key(&self, value: i128) -> u128 {
+        if self.is_signed() {
+            (value as u128) ^ (1 << 127)
+        } else {
+            value as u128
+        }
+    }
+
+    // The value behind an order key, written out
+    pub fn key_to_string(&self, key: u128) -> String {
+        if self.is_signed() {
+            ((key ^ (1 << 127)) as i128).to_string()
+        } else {
+            key.to_string()
+        }
+    }
+
+    // Order keys of the smallest and largest value of the type
+    pub fn key_bounds(&self) -> (u128, u128) {
+        match *self {
+            Type::Unsigned(width) if width < 128 => (0, (1 << width) - 1),
+            Type::Signed(width) if width < 128 => {
+                (self.order_key(-(1 << (width - 1))), self.order_key((1 << (width - 1)) - 1))
+            }
+            _ => (0, u128::MAX),
+        }
+    }
+
+    // The type of a literal right after a `-` that only fits once negated:
+    // the most negative value of a signed type written with its suffix, like
+    // -128s8, or of s128 when unsuffixed. The unsuffixed one stays an untyped
+    // literal instead of being typed b128 like any other literal that large.
+    pub fn negated_minimum(value: u128, suffix: Option<TokenType>) -> Option<Type> {
+        match suffix.and_then(Type::from_kind) {
+            Some(tp @ Type::Signed(width)) if value == 1u128 << (width - 1) => Some(tp),
+            None if value == 1u128 << 127 => Some(Type::Literal),
+            _ => None,
+        }
+    }
+
+    // Whether a literal value can be represented without wrapping. Untyped
+    // values are plain i128s, so every non-negative one fits b128 and every
+    // one fits s128.
+    pub fn fits(&self, value: i128) -> bool {
+        match self {
+            Type::Unsigned(width) if *width < 128 => value >= 0 && value < (1i128 << width),
+            Type::Unsigned(_) => value >= 0,
+            Type::Signed(width) if *width < 128 => {
+                value >= -(1i128 << (width - 1)) && value < (1i128 << (width - 1))
+            }
+            _ => true,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Unsigned(width) => write!(f, "b{}", width),
+            Type::Signed(width) => write!(f, "s{}", width),
+            Type::Literal => write!(f, "literal"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn new(tp: Type, constant: Option<i128>, node: NodeId) -> Self {
+        Expr { tp, constant, node }
+    }
+
+    // Unsuffixed literals stay untyped until they meet a typed operand, except
+    // those too large for anything but b128.
+    pub fn literal(token: &Token, node: NodeId) -> Self {
+        let (value, suffix) = match token {
+            Token::Number(value, suffix, _, _) => (*value, suffix.and_then(Type::from_kind)),
+            _ => panic!("Expected number, found {:?}", token),
+        };
+        let tp = match suffix {
+            Some(tp) => tp,
+            None if value > i128::MAX as u128 => Type::Unsigned(128),
+            None => return Expr::new(Type::Literal, Some(value as i128), node),
+        };
+        if tp != Type::Unsigned(128) && (value > i128::MAX as u128 || !tp.fits(value as i128)) {
+            let (line, column) = token.position();
+            panic!("Literal {} does not fit in {} at line {}, column {}", value, tp, line, column);
+        }
+        Expr::new(tp, Some(value as i128), node)
+    }
+
+    // An untyped literal combined with or assigned to a typed value has to be
+    // representable in that type.
+    fn check_literal_fits(&self, tp: Type, op: &Token) {
+        if let (Type::Literal, Some(value)) = (self.tp, self.constant) {
+            if !tp.fits(value) {
+                let (line, column) = op.position();
+                panic!("Literal {} does not fit in {} at line {}, column {}", value, tp, line, column);
+            }
+        }
+    }
+
+    pub fn boolean(constant: Option<bool>, node: NodeId) -> Self {
+        Expr::new(Type::Unsigned(1), constant.map(|value| value as i128), node)
+    }
+
+    // Explicit conversion to another width or signedness. Narrowing keeps the
+    // low bits of the value. Widening zero extends unsigned sources and sign
+    // extends signed ones, which falls out of values being kept normalized for
+    // their own type.
+    pub fn convert(self, tp: Type, node: NodeId) -> Expr {
+        Expr::new(tp, self.constant.map(|value| tp.truncate(value)), node)
+    }
+
+    // Widening to a type of the same signedness happens implicitly. Anything
+    // that could drop bits or reinterpret the sign bit needs an explicit cast.
+    pub fn check_assignable(&self, target: Type, op: &Token) {
+        self.check_literal_fits(target, op);
+        let allowed = match (self.tp, target) {
+            (Type::Literal, _) => true,
+            (Type::Unsigned(from), Type::Unsigned(to)) | (Type::Signed(from), Type::Signed(to)) => from <= to,
+            _ => false,
+        };
+        if !allowed {
+            let (line, column) = op.position();
+            panic!("Cannot assign {} value to {} at line {}, column {}, use an explicit cast: `as {}`",
+                self.tp, target, line, column, target);
+        }
+    }
+
+    pub fn negate(self, op: &Token, node: NodeId) -> Expr {
+        let (line, column) = op.position();
+        if !self.tp.is_signed() {
+            panic!("Cannot negate unsigned {} value at line {}, column {}", self.tp, line, column);
+        }
+        if let Some(value) = self.constant {
+            if !matches!(value.checked_neg(), Some(negated) if self.tp.fits(negated)) {
+                panic!("Constant expression overflows {} at line {}, column {}", self.tp, line, column);
+            }
+        }
+        Expr::new(self.tp, self.constant.map(|value| self.tp.truncate(value.wrapping_neg())), node)
+    }
+
+    // Types and folds `left op right`. Shifts keep the type of the left
+    // operand and are logical: bits shifted past the width are dropped, they
+    // never rotate back in, so any shift by the full width or more gives 0.
+    // A constant shift amount that large is almost certainly a mistake and is
+    // rejected at compile time. Right shifts of signed values are arithmetic.
+    // An untyped literal has no width to shift within, so it can only be
+    // shifted by a constant amount, which folds.
+    pub fn binary(op: &Token, left: Expr, right: Expr, node: NodeId) -> Expr {
+        let (line, column) = op.position();
+        match op.kind() {
+            TokenType::And | TokenType::Or => {
+                let constant = match (left.constant, right.constant) {
+                    (Some(l), Some(r)) if op.kind() == TokenType::And => Some(l != 0 && r != 0),
+                    (Some(l), Some(r)) => Some(l != 0 || r != 0),
+                    _ => None,
+                };
+                return Expr::boolean(constant, node);
+            }
+            TokenType::ShiftLeft | TokenType::ShiftRight => {
+                let tp = left.tp;
+                if let (Type::Literal, Some(value), None) = (tp, left.constant, right.constant) {
+                    panic!("Literal {} is shifted by an amount that is not constant at line {}, column {}, \
+                        give it a width with a suffix like {}b32 or a cast", value, line, column, value);
+                }
+                if let Some(amount) = right.constant {
+                    let width = tp.width().unwrap_or(128);
+                    if amount < 0 || amount as usize >= width {
+                        panic!("Shift amount {} out of range for {} at line {}, column {}",
+                            amount, tp, line, column);
+                    }
+                }
+                let constant = match (left.constant, right.constant) {
+                    (Some(l), Some(r)) => fold(op.kind(), tp, l, r),
+                    _ => None,
+                };
+                return Expr::new(tp, constant, node);
+            }
+            _ => {}
+        }
+
+        let tp = match Type::unify(left.tp, right.tp) {
+            Some(tp) => tp,
+            None => panic!("Mismatched operands {} and {} at line {}, column {}, convert one of them explicitly",
+                left.tp, right.tp, line, column),
+        };
+        left.check_literal_fits(tp, op);
+        right.check_literal_fits(tp, op);
+        let comparison = matches!(op.kind(), TokenType::Equal | TokenType::NotEqual | TokenType::Greater
+            | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual);
+        let (l, r) = match (left.constant, right.constant) {
+            (Some(l), Some(r)) => (l, r),
+            _ if comparison => return Expr::boolean(None, node),
+            _ => return Expr::new(tp, None, node),
+        };
+        match fold(op.kind(), tp, l, r) {
+            Some(value) if comparison => Expr::boolean(Some(value != 0), node),
+            Some(_) if wraps(op.kind(), tp, l, r) => {
+                panic!("Constant expression overflows {} at line {}, column {}", tp, line, column)
+            }
+            Some(value) => Expr::new(tp, Some(value), node),
+            None => panic!("Division by zero at line {}, column {}", line, column),
+        }
+    }
+}
+
+// Whether the exact result of `l op r` falls outside `tp`, so folding it
+// wraps around. Only arithmetic can overflow; shifts drop the bits they move
+// out by design.
+fn wraps(op: TokenType, tp: Type, l: i128, r: i128) -> bool {
+    if tp == Type::Unsigned(128) {
+        let (l, r) = (l as u128, r as u128);
+        return match op {
+            TokenType::Plus => l.checked_add(r).is_none(),
+            TokenType::Minus => l < r,
+            TokenType::Star => l.checked_mul(r).is_none(),
+            _ => false,
+        };
+    }
+    let exact = match op {
+        TokenType::Plus => l.checked_add(r),
+        TokenType::Minus => l.checked_sub(r),
+        TokenType::Star => l.checked_mul(r),
+        TokenType::Slash if tp.is_signed() => l.checked_div(r),
+        _ => return false,
+    };
+    !matches!(exact, Some(value) if tp.fits(value))
+}
+
+// Applies a binary operator to two values of type `tp`, for constant folding
+// and at run time alike. Comparisons give 1 or 0. Returns None on division
+// by zero.
+pub fn fold(op: TokenType, tp: Type, l: i128, r: i128) -> Option<i128> {
+    // Values are kept sign extended for signed types, so only unsigned
+    // values have to be compared and divided as u128.
+    let signed = tp.is_signed();
+    let ordering = if signed { l.cmp(&r) } else { (l as u128).cmp(&(r as u128)) };
+    let value = match op {
+        TokenType::Equal => ordering.is_eq() as i128,
+        TokenType::NotEqual => ordering.is_ne() as i128,
+        TokenType::Greater => ordering.is_gt() as i128,
+        TokenType::GreaterEqual => ordering.is_ge() as i128,
+        TokenType::Less => ordering.is_lt() as i128,
+        TokenType::LessEqual => ordering.is_le() as i128,
+        TokenType::And => (l != 0 && r != 0) as i128,
+        TokenType::Or => (l != 0 || r != 0) as i128,
+        TokenType::Plus => l.wrapping_add(r),
+        TokenType::Minus => l.wrapping_sub(r),
+        TokenType::Star => l.wrapping_mul(r),
+        TokenType::Slash | TokenType::Percent => {
+            if r == 0 {
+                return None;
+            }
+            match (op, signed) {
+                (TokenType::Slash, true) => l.wrapping_div(r),
+                (TokenType::Slash, false) => ((l as u128) / (r as u128)) as i128,
+                (_, true) => l.wrapping_rem(r),
+                (_, false) => ((l as u128) % (r as u128)) as i128,
+            }
+        }
+        TokenType::Ampersand => l & r,
+        TokenType::Pipe => l | r,
+        TokenType::Caret => l ^ r,
+        // Amounts of the full width or more shift every bit out, the
+        // amount being read as unsigned
+        TokenType::ShiftLeft | TokenType::ShiftRight => {
+            let amount = r as u128;
+            let in_range = amount < tp.width().unwrap_or(128) as u128;
+            match (op, signed) {
+                (TokenType::ShiftLeft, _) if in_range => l << amount,
+                (TokenType::ShiftLeft, _) => 0,
+                (_, true) => l >> amount.min(127),
+                (_, false) if in_range => ((l as u128) >> amount) as i128,
+                (_, false) => 0,
+            }
+        }
+        _ => panic!("Unexpected binary operator: {:?}", op),
+    };
+    Some(tp.truncate(value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldType, StructType, Type};
+    use crate::compiler::interner::Symbol;
+
+    fn offsets(layout: &StructType) -> Vec<usize> {
+        layout.fields.iter().map(|field| field.offset).collect()
+    }
+
+    #[test]
+    fn fields_are_aligned_to_their_size() {
+        let inner = StructType::layout(Symbol::intern("In"), &[
+            (Symbol::intern("a"), FieldType::Integer(Type::Unsigned(8))),
+            (Symbol::intern("b"), FieldType::Integer(Type::Unsigned(32))),
+        ], &[]);
+        assert_eq!((offsets(&inner), inner.size, inner.align), (vec![0, 4], 8, 4));
+
+        // a nested struct is aligned like its widest field, and the size is
+        // padded to a multiple of the alignment
+        let outer = StructType::layout(Symbol::intern("Out"), &[
+            (Symbol::intern("c"), FieldType::Integer(Type::Unsigned(1))),
+            (Symbol::intern("inner"), FieldType::Struct(0)),
+            (Symbol::intern("d"), FieldType::Integer(Type::Signed(16))),
+        ], &[&inner]);
+        assert_eq!((offsets(&outer), outer.size, outer.align), (vec![0, 4, 12], 16, 4));
+        assert_eq!(outer.slots, 4);
+    }
+}
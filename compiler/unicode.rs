@@ -0,0 +1,137 @@
+use crate::compiler::unicode_tables::{COMBINING_CLASS, COMPOSITION, DECOMPOSITION, XID_CONTINUE, XID_START};
+use std::cmp::Ordering;
+
+// Identifiers follow UAX #31: they start with an XID_Start character or `_`
+// and continue with XID_Continue characters. Two spellings of the same name
+// (precomposed `é` or `e` followed by a combining acute accent) are the same
+// identifier, so names are interned in Normalization Form C.
+
+pub fn is_xid_start(ch: char) -> bool {
+    in_ranges(XID_START, ch)
+}
+
+pub fn is_xid_continue(ch: char) -> bool {
+    in_ranges(XID_CONTINUE, ch)
+}
+
+fn in_ranges(ranges: &[(char, char)], ch: char) -> bool {
+    ranges.binary_search_by(|&(start, end)| {
+        if end < ch {
+            Ordering::Less
+        } else if start > ch {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }).is_ok()
+}
+
+fn combining_class(ch: char) -> u8 {
+    if ch.is_ascii() {
+        return 0;
+    }
+    match COMBINING_CLASS.binary_search_by(|&(start, end, _)| {
+        if end < ch {
+            Ordering::Less
+        } else if start > ch {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }) {
+        Ok(index) => COMBINING_CLASS[index].2,
+        Err(_) => 0,
+    }
+}
+
+// Hangul syllables decompose and compose arithmetically instead of through
+// the tables.
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+fn decompose(ch: char, out: &mut Vec<char>) {
+    let code = ch as u32;
+    if (S_BASE..S_BASE + S_COUNT).contains(&code) {
+        let index = code - S_BASE;
+        out.push(std::char::from_u32(L_BASE + index / N_COUNT).unwrap());
+        out.push(std::char::from_u32(V_BASE + (index % N_COUNT) / T_COUNT).unwrap());
+        if !index.is_multiple_of(T_COUNT) {
+            out.push(std::char::from_u32(T_BASE + index % T_COUNT).unwrap());
+        }
+        return;
+    }
+    match DECOMPOSITION.binary_search_by(|&(composite, _, _)| composite.cmp(&ch)) {
+        Ok(index) => {
+            let (_, first, second) = DECOMPOSITION[index];
+            decompose(first, out);
+            if second != '\0' {
+                decompose(second, out);
+            }
+        }
+        Err(_) => out.push(ch),
+    }
+}
+
+fn compose(first: char, second: char) -> Option<char> {
+    let (first_code, second_code) = (first as u32, second as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&first_code) && (V_BASE..V_BASE + V_COUNT).contains(&second_code) {
+        let index = (first_code - L_BASE) * N_COUNT + (second_code - V_BASE) * T_COUNT;
+        return std::char::from_u32(S_BASE + index);
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&first_code) && (first_code - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&second_code) {
+        return std::char::from_u32(first_code + second_code - T_BASE);
+    }
+    COMPOSITION.binary_search_by(|&(a, b, _)| (a, b).cmp(&(first, second)))
+        .ok()
+        .map(|index| COMPOSITION[index].2)
+}
+
+// Canonical decomposition, canonical ordering of combining marks, then
+// canonical composition, as described in UAX #15.
+pub fn nfc(text: &str) -> String {
+    let mut chars = Vec::with_capacity(text.len());
+    for ch in text.chars() {
+        decompose(ch, &mut chars);
+    }
+
+    for index in 1..chars.len() {
+        let class = combining_class(chars[index]);
+        let mut position = index;
+        while class != 0 && position > 0 && combining_class(chars[position - 1]) > class {
+            chars.swap(position - 1, position);
+            position -= 1;
+        }
+    }
+
+    // A mark combines with the last starter unless a character in between
+    // has a combining class of zero or at least its own.
+    let mut result: Vec<char> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for ch in chars {
+        let class = combining_class(ch);
+        if let Some(index) = starter {
+            let blocked = index + 1 != result.len() && (last_class == 0 || last_class >= class);
+            if !blocked {
+                if let Some(composite) = compose(result[index], ch) {
+                    result[index] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(result.len());
+        }
+        last_class = class;
+        result.push(ch);
+    }
+    result.into_iter().collect()
+}
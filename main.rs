@@ -1,34 +1,119 @@
+mod bench;
 mod compiler;
 
 use std::fs;
 use std::env;
+use std::panic;
+use std::thread;
 
-use crate::compiler::{Lexer, Parser, Token};
+use crate::compiler::{Lexer, Parser};
+use crate::compiler::lexer::decode_source;
+use crate::compiler::lint;
+use crate::compiler::interpreter::{self, Interpreter};
+
+// `--max-depth=N` limits how deeply calls of the program may nest
+fn max_depth() -> usize {
+    match env::args().find(|arg| arg.starts_with("--max-depth=")) {
+        Some(arg) => match arg["--max-depth=".len()..].parse() {
+            Ok(depth) if depth > 0 => depth,
+            _ => panic!("Invalid {}, expected a positive number of calls", arg),
+        },
+        None => interpreter::DEFAULT_MAX_DEPTH,
+    }
+}
+
+// `--no-lint` turns the warnings off
+fn lint_warnings(source: &str, mut args: impl Iterator<Item = String>) -> Vec<String> {
+    if args.any(|arg| arg == "--no-lint") {
+        return Vec::new();
+    }
+    lint::hyphenated_identifiers(source)
+}
 
 fn main() -> std::io::Result<()> {
+    if env::args().nth(1).as_deref() == Some("--bench") {
+        bench::run(env::args().nth(2));
+        return Ok(())
+    }
+    // everything runs on a thread with room for the deepest calls allowed,
+    // names are interned per thread so the compiler goes along
+    let max_depth = max_depth();
+    let compiler = thread::Builder::new()
+        .name("main".to_string())
+        .stack_size(interpreter::stack_size(max_depth))
+        .spawn(move || compile(max_depth))
+        .unwrap_or_else(|error| panic!("Cannot reserve a stack for {} nested calls, lower --max-depth: {}", max_depth, error));
+    match compiler.join() {
+        Ok(result) => result,
+        // the panic has been reported already
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+fn compile(max_depth: usize) -> std::io::Result<()> {
     let file_path = env::args().nth(1).expect("File path missing from command line arguments.");
-    let file_content = fs::read_to_string(file_path).expect("Should read file");
-    let mut lexer = Lexer::new(&file_content);
+    let file_bytes = fs::read(file_path).expect("Should read file");
+    let file_content = match decode_source(&file_bytes) {
+        Ok(source) => source,
+        Err(error) => panic!("{}", error),
+    };
+    if env::args().any(|arg| arg == "--lossless") {
+        // echo the source back token by token, trivia included
+        let mut lexer = Lexer::with_trivia(file_content);
+        while let Some(token) = lexer.next() {
+            if let Err(error) = token {
+                panic!("{}", error);
+            }
+            print!("{}", lexer.lexeme());
+        }
+        return Ok(())
+    }
+    for warning in lint_warnings(file_content, env::args()) {
+        eprintln!("warning: {}", warning);
+    }
+    // `--tokens` lists the tokens on stderr, leaving stdout to the program
+    let dump_tokens = env::args().any(|arg| arg == "--tokens");
+    let lexer = Lexer::new(file_content);
     let mut tokens = Vec::new();
-    loop{
-        let token = lexer.next_token();
-        tokens.push(token.clone());
+    for token in lexer {
         match token {
-            Token::EOF(_, _) => {
-                println!("LEXER ENDED");
-                println!("tokens: {:?}", tokens);
-                break;
+            Ok(token) => {
+                if dump_tokens {
+                    eprintln!("{:?}", token);
+                }
+                tokens.push(token);
             }
-            _ => println!("{:?}", token),
+            Err(error) => panic!("{}", error),
         }
-
-        println!("{:?}", token);
     }
     let mut parser = Parser::new(&tokens);
-    loop {
-        //println!("tokens: {:?}", tokens);
-        parser.parse();
-        println!("PARSE CONCLUDED");
-        return Ok(())
+    let root = parser.parse();
+    if env::args().any(|arg| arg == "--docs") {
+        print!("{}", parser.reference_docs());
+    }
+    if env::args().any(|arg| arg == "--data") {
+        print!("{}", parser.data_section().dump());
+    }
+    if env::args().any(|arg| arg == "--ast") {
+        print!("{}", parser.ast().dump(root));
+    }
+    if env::args().any(|arg| arg == "--run") {
+        if let Some(status) = Interpreter::new(parser.ast(), max_depth).run(root) {
+            std::process::exit(status as i32);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lint_warnings;
+
+    #[test]
+    fn no_lint_silences_the_warnings() {
+        let source = "function main() { b8 total-sum = 1; }";
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter();
+        assert_eq!(lint_warnings(source, args(&["main", "file.27"])).len(), 1);
+        assert!(lint_warnings(source, args(&["main", "file.27", "--no-lint"])).is_empty());
     }
 }
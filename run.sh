@@ -1 +1 @@
-./main test_code.27
+rustc main.rs -o main && ./main test_code.27 "$@"
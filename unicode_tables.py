@@ -0,0 +1,96 @@
+#!/usr/bin/env python3
+# Regenerates compiler/unicode_tables.rs from the Unicode database bundled
+# with Python:
+#     python3 unicode_tables.py > compiler/unicode_tables.rs
+import sys
+import unicodedata
+
+MAX = sys.maxunicode + 1
+
+
+def ranges(predicate):
+    result = []
+    start = None
+    for cp in range(MAX):
+        if predicate(cp):
+            if start is None:
+                start = cp
+        elif start is not None:
+            result.append((start, cp - 1))
+            start = None
+    if start is not None:
+        result.append((start, MAX - 1))
+    return result
+
+
+def is_char(cp):
+    return not 0xD800 <= cp <= 0xDFFF
+
+
+# str.isidentifier implements UAX #31: XID_Start (plus `_`) followed by
+# XID_Continue
+def xid_start(cp):
+    return is_char(cp) and cp != ord('_') and chr(cp).isidentifier()
+
+
+def xid_continue(cp):
+    return is_char(cp) and ('a' + chr(cp)).isidentifier()
+
+
+def combining_classes():
+    result = []
+    start = None
+    for cp in range(MAX + 1):
+        ccc = unicodedata.combining(chr(cp)) if cp < MAX and is_char(cp) else 0
+        if start is not None and ccc != start[1]:
+            result.append((start[0], cp - 1, start[1]))
+            start = None
+        if ccc and start is None:
+            start = (cp, ccc)
+    return result
+
+
+def decompositions():
+    result = []
+    for cp in range(MAX):
+        if not is_char(cp):
+            continue
+        decomposition = unicodedata.decomposition(chr(cp))
+        if not decomposition or decomposition.startswith('<'):
+            continue
+        parts = [int(part, 16) for part in decomposition.split()]
+        result.append((cp, parts[0], parts[1] if len(parts) > 1 else 0))
+    return result
+
+
+# Pairs that NFC recomposes: two-character canonical decompositions whose
+# composite survives normalization, which leaves out composition exclusions
+def compositions(decomposed):
+    result = []
+    for cp, first, second in decomposed:
+        if second and unicodedata.normalize('NFC', chr(cp)) == chr(cp):
+            result.append((first, second, cp))
+    return sorted(result)
+
+
+def char(cp):
+    return "'\\u{%X}'" % cp
+
+
+def table(name, tp, rows, per_line):
+    print('pub static %s: &[%s] = &[' % (name, tp))
+    for index in range(0, len(rows), per_line):
+        line = ' '.join('(%s),' % ', '.join(rows[i]) for i in range(index, min(index + per_line, len(rows))))
+        print('    ' + line)
+    print('];')
+    print()
+
+
+decomposed = decompositions()
+print('// Generated by unicode_tables.py from Unicode %s, do not edit by hand.' % unicodedata.unidata_version)
+print()
+table('XID_START', '(char, char)', [(char(a), char(b)) for a, b in ranges(xid_start)], 4)
+table('XID_CONTINUE', '(char, char)', [(char(a), char(b)) for a, b in ranges(xid_continue)], 4)
+table('COMBINING_CLASS', '(char, char, u8)', [(char(a), char(b), str(c)) for a, b, c in combining_classes()], 3)
+table('DECOMPOSITION', '(char, char, char)', [(char(a), char(b), char(c)) for a, b, c in decomposed], 3)
+table('COMPOSITION', '(char, char, char)', [(char(a), char(b), char(c)) for a, b, c in compositions(decomposed)], 3)